@@ -1,11 +1,16 @@
 use std::{
     env,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use eyre::{eyre, OptionExt};
-use teller_core::{exec, export, teller::Teller};
+use teller_core::{
+    config::Config,
+    doctor, exec, export,
+    teller::{Selection, Teller},
+};
 use teller_providers::{config::KV, providers::ProviderKind};
 
 use crate::{
@@ -25,6 +30,14 @@ pub struct Cli {
     #[arg(long)]
     pub verbose: bool,
 
+    /// Environment profile to apply from `environments` (defaults to `TELLER_ENV`)
+    #[arg(long, global = true)]
+    pub env: Option<String>,
+
+    /// Fail when maps of the same priority provide the same key
+    #[arg(long, global = true)]
+    pub strict: bool,
+
     /// A teller command
     #[command(subcommand)]
     pub command: Commands,
@@ -39,6 +52,35 @@ pub enum Commands {
         /// Run command as shell command
         #[arg(short, long)]
         shell: bool,
+        /// Redact secrets from the command's stdout and stderr
+        #[arg(long)]
+        redact: bool,
+        /// Keep this variable when resetting the environment (repeatable)
+        #[arg(long, value_name = "VAR")]
+        keep: Vec<String>,
+        /// Keep variables matching this pattern, e.g. 'XDG_*', when resetting the environment (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        keep_pattern: Vec<String>,
+        /// Keep only the variables given by `--keep`, `--keep-pattern` and the `exec` config when resetting
+        #[arg(long)]
+        no_keep_defaults: bool,
+        /// Strip inherited variables matching this pattern, e.g. '*_TOKEN' (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        deny: Vec<String>,
+        /// Deliver every key as a file in a private directory under DIR, pointed to by KEY_FILE
+        #[arg(long, value_name = "DIR")]
+        files: Option<PathBuf>,
+        /// Keep collecting while the command runs, and act on it when values change
+        #[arg(long)]
+        watch: bool,
+        /// The longest time between two collections when watching, e.g. 30s, 5m
+        #[arg(long, requires = "watch", default_value = "60s", value_parser = humantime::parse_duration)]
+        interval: Duration,
+        /// What to do when values change: restart, or signal:<NAME> such as signal:HUP
+        #[arg(long, requires = "watch", default_value = "restart")]
+        on_change: exec::OnChange,
+        #[command(flatten)]
+        select: SelectArgs,
         /// The command to run
         #[arg(value_name = "COMMAND", raw = true)]
         command: Vec<String>,
@@ -51,6 +93,11 @@ pub enum Commands {
         /// The format to export to
         #[arg(value_enum, index = 1)]
         format: Format,
+        /// Export values above the policy's `export_max` sensitivity
+        #[arg(long)]
+        allow_sensitive: bool,
+        #[command(flatten)]
+        select: SelectArgs,
     },
     /// Redact text using fetched secrets
     Redact {
@@ -60,6 +107,8 @@ pub enum Commands {
         /// Output file (stdout if none given)
         #[arg(short, long)]
         out: Option<String>,
+        #[command(flatten)]
+        select: SelectArgs,
     },
 
     /// Render a key-value aware template
@@ -70,16 +119,33 @@ pub enum Commands {
         /// Output destination (stdout if none given)
         #[arg(short, long)]
         out: Option<String>,
+        #[command(flatten)]
+        select: SelectArgs,
     },
 
     /// Export compatible with ENV
-    Env {},
+    Env {
+        /// Export values above the policy's `export_max` sensitivity
+        #[arg(long)]
+        allow_sensitive: bool,
+        #[command(flatten)]
+        select: SelectArgs,
+    },
 
     /// Print all currently accessible data
-    Show {},
+    Show {
+        #[command(flatten)]
+        select: SelectArgs,
+    },
 
     /// Export as source-able shell script
-    Sh {},
+    Sh {
+        /// Export values above the policy's `export_max` sensitivity
+        #[arg(long)]
+        allow_sensitive: bool,
+        #[command(flatten)]
+        select: SelectArgs,
+    },
 
     /// Create a new Teller configuration
     New(NewArgs),
@@ -116,6 +182,140 @@ pub enum Commands {
         #[arg(long, short)]
         replace: bool,
     },
+
+    /// Print the key-values of a single map
+    Get {
+        /// The map to read
+        #[arg(value_name = "PROVIDER/MAP_ID")]
+        location: String,
+
+        /// Only print these keys
+        keys: Vec<String>,
+
+        /// Read a specific version instead of the current (or pinned) one
+        #[arg(long)]
+        version: Option<String>,
+    },
+
+    /// List the versions a provider keeps for a map
+    History {
+        /// The map to inspect
+        #[arg(value_name = "PROVIDER/MAP_ID")]
+        location: String,
+
+        /// Only list versions of this key
+        key: Option<String>,
+    },
+
+    /// Inspect the layered configuration
+    #[command(subcommand)]
+    Config(ConfigCommands),
+
+    /// Manage the local cache of provider reads
+    #[command(subcommand)]
+    Cache(CacheCommands),
+
+    /// List paths and keys stored in a provider
+    Ls {
+        /// Provider name
+        provider: String,
+
+        /// Path prefix to list, as the provider writes it (e.g. `/app/prod` for SSM)
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+
+    /// Check that every provider loads and every map resolves
+    Doctor {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Seconds a provider gets to load, and each map gets to answer
+        #[arg(long, default_value_t = teller_core::doctor::DEFAULT_TIMEOUT.as_secs())]
+        timeout: u64,
+    },
+
+    /// Check collected keys against the `schema` and report every violation
+    Check {
+        /// Print the violations as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        select: SelectArgs,
+    },
+
+    /// Copy every `source` map into the maps that declare it as their `sink`
+    Sync {
+        /// Delete data at each sink before copying
+        #[arg(long, short)]
+        replace: bool,
+
+        /// Only print what would be copied
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Narrows the providers and maps a command reads from. Flattened into the commands that
+/// collect, rather than global: `put` and `delete` take `--map-id` as their target, and
+/// commands that read a single map would silently ignore a selection.
+#[derive(Debug, Clone, Default, Args)]
+pub struct SelectArgs {
+    /// Only read maps of these providers
+    #[arg(long = "provider", value_delimiter = ',')]
+    pub providers: Vec<String>,
+
+    /// Only read these maps, as `<map id>` or `<provider name>/<map id>`
+    #[arg(long = "map-id", value_delimiter = ',')]
+    pub map_ids: Vec<String>,
+
+    /// Only read maps with one of these tags
+    #[arg(long = "tag", value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    /// Never read these maps, as `<map id>` or `<provider name>/<map id>`
+    #[arg(long = "exclude-map", value_delimiter = ',')]
+    pub exclude_maps: Vec<String>,
+}
+
+impl From<SelectArgs> for Selection {
+    fn from(args: SelectArgs) -> Self {
+        Self {
+            providers: args.providers,
+            map_ids: args.map_ids,
+            tags: args.tags,
+            exclude_maps: args.exclude_maps,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommands {
+    /// Print the configuration files in the order they are layered
+    Show {
+        /// Print the configuration resulting from all layers, includes and `--env`
+        #[arg(long)]
+        resolved: bool,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum CacheCommands {
+    /// Remove cached reads
+    Clear {
+        /// Only clear this provider
+        provider: Option<String>,
+    },
+}
+
+fn parse_map_location(location: &str) -> eyre::Result<(&str, &str)> {
+    location.split_once('/').ok_or_else(|| {
+        eyre!(
+            "cannot parse '{}', did you format it as: '<provider name>/<map id>' ?",
+            location
+        )
+    })
 }
 
 fn parse_key_val<T, U>(
@@ -163,6 +363,8 @@ pub struct ScanArgs {
     /// Output matches as JSON
     #[arg(short, long)]
     pub json: bool,
+    #[command(flatten)]
+    pub select: SelectArgs,
 }
 
 const DEFAULT_FILE_PATH: &str = ".teller.yml";
@@ -204,18 +406,68 @@ fn find_file_upwards(start_dir: &Path, config_filename: &str) -> eyre::Result<Op
     }
 }
 
-async fn load_teller(config: Option<String>) -> eyre::Result<Teller> {
-    let config_arg = if let Some(config) = config {
-        config
-    } else {
-        find_file_upwards(env::current_dir()?.as_path(), DEFAULT_FILE_PATH)?
-            .ok_or_eyre("cannot find configuration from current folder and up to root")?
-            .to_string_lossy()
-            .to_string()
-    };
-
-    let config_path = Path::new(&config_arg);
-    let teller = Teller::from_yaml(config_path).await?;
+fn user_config_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("teller").join("config.yml"))
+        .filter(|path| path.exists())
+}
+
+/// Configuration files to layer, each one merged over those before it. With `--config`
+/// only that file, otherwise the user-level `~/.config/teller/config.yml` as a base, then
+/// every `.teller.yml` from the repository root down to the current folder.
+fn config_layers(config: Option<String>) -> eyre::Result<Vec<PathBuf>> {
+    if let Some(config) = config {
+        return Ok(vec![PathBuf::from(config)]);
+    }
+    let current_dir = env::current_dir()?;
+    let nearest = find_file_upwards(current_dir.as_path(), DEFAULT_FILE_PATH)?
+        .ok_or_eyre("cannot find configuration from current folder and up to root")?;
+    let mut repo = vec![nearest.clone()];
+    if let Some(root) = current_dir
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+    {
+        let outer = nearest.parent().and_then(Path::parent);
+        for dir in outer.into_iter().flat_map(Path::ancestors) {
+            if !dir.starts_with(root) {
+                break;
+            }
+            let layer = dir.join(DEFAULT_FILE_PATH);
+            if layer.exists() {
+                repo.insert(0, layer);
+            }
+        }
+    }
+    let mut layers: Vec<PathBuf> = user_config_path().into_iter().collect();
+    layers.extend(repo);
+    Ok(layers)
+}
+
+/// Load the configuration, with the environment profile from `--env` or `TELLER_ENV`
+fn load_config(args: &Cli) -> eyre::Result<Config> {
+    let config = Config::from_layers(&config_layers(args.config.clone())?)?;
+    let environment = args
+        .env
+        .clone()
+        .or_else(|| env::var("TELLER_ENV").ok())
+        .filter(|name| !name.is_empty());
+    Ok(match environment {
+        Some(name) => config.with_environment(&name)?,
+        None => config,
+    })
+}
+
+async fn load_teller(args: &Cli) -> eyre::Result<Teller> {
+    let teller = Teller::from_config(&load_config(args)?)
+        .await?
+        .with_strict(args.strict);
+    Ok(teller)
+}
+
+async fn load_selected(args: &Cli, select: SelectArgs) -> eyre::Result<Teller> {
+    let teller = load_teller(args).await?.with_selection(select.into())?;
     Ok(teller)
 }
 
@@ -231,17 +483,39 @@ pub async fn run(args: &Cli) -> eyre::Result<Response> {
         Commands::Run {
             reset,
             shell,
+            redact,
+            keep,
+            keep_pattern,
+            no_keep_defaults,
+            deny,
+            files,
+            watch,
+            interval,
+            on_change,
+            select,
             command,
         } => {
-            let teller = load_teller(args.config.clone()).await?;
+            let teller = load_selected(args, select).await?;
             let pwd = std::env::current_dir()?;
             let opts = exec::Opts {
                 pwd: pwd.as_path(),
                 sh: shell,
                 reset_env: reset,
                 capture: false,
+                redact,
+                env: exec::ExecConfig {
+                    keep,
+                    keep_patterns: keep_pattern,
+                    keep_defaults: !no_keep_defaults,
+                    deny,
+                },
+                files_dir: files.as_deref(),
+                watch: watch.then_some(exec::Watch {
+                    interval,
+                    on_change,
+                }),
             };
-            teller
+            let output = teller
                 .run(
                     command
                         .iter()
@@ -251,55 +525,83 @@ pub async fn run(args: &Cli) -> eyre::Result<Response> {
                     &opts,
                 )
                 .await?;
-            Response::ok()
+            // exit the way the command did, so wrappers can tell its failures from ours
+            Ok(Response {
+                code: exec::exit_code(&output.status),
+                message: None,
+            })
         }
         Commands::Scan(cmdargs) => {
-            let teller = load_teller(args.config.clone()).await?;
+            let teller = load_selected(args, cmdargs.select.clone()).await?;
             scan::run(&teller, &cmdargs).await
         }
-        Commands::Export { format } => {
+        Commands::Export {
+            format,
+            allow_sensitive,
+            select,
+        } => {
             let teller_format = match format {
                 Format::CSV => export::Format::CSV,
                 Format::YAML => export::Format::YAML,
                 Format::JSON => export::Format::JSON,
                 Format::ENV => export::Format::ENV,
             };
-            let teller = load_teller(args.config.clone()).await?;
-            let out = teller.export(&teller_format).await?;
+            let teller = load_selected(args, select).await?;
+            let out = teller.export(&teller_format, allow_sensitive).await?;
             Response::ok_with_message(out)
         }
-        Commands::Redact { in_file, out } => {
-            let teller = load_teller(args.config.clone()).await?;
+        Commands::Redact {
+            in_file,
+            out,
+            select,
+        } => {
+            let teller = load_selected(args, select).await?;
             teller
                 .redact(&mut or_stdin(in_file)?, &mut or_stdout(out)?)
                 .await?;
             Response::ok()
         }
-        Commands::Template { in_file, out } => {
+        Commands::Template {
+            in_file,
+            out,
+            select,
+        } => {
             let mut input = String::new();
             or_stdin(in_file)?.read_to_string(&mut input)?;
-            let teller = load_teller(args.config.clone()).await?;
+            let teller = load_selected(args, select).await?;
             let rendered = teller.template(&input).await?;
             let mut out = or_stdout(out)?;
             out.write_all(rendered.as_bytes())?;
             out.flush()?;
             Response::ok()
         }
-        Commands::Env {} => {
-            let teller = load_teller(args.config.clone()).await?;
-            let out = teller.export(&export::Format::ENV).await?;
+        Commands::Env {
+            allow_sensitive,
+            select,
+        } => {
+            let teller = load_selected(args, select).await?;
+            let out = teller.export(&export::Format::ENV, allow_sensitive).await?;
             Response::ok_with_message(out)
         }
         Commands::New(new_args) => new::run(&new_args),
-        Commands::Show {} => {
-            let teller = load_teller(args.config.clone()).await?;
-            let kvs = teller.collect().await?;
-            io::print_kvs(&kvs);
+        Commands::Show { select } => {
+            let teller = load_selected(args, select).await?;
+            if let Some(environment) = &teller.config().environment {
+                println!("environment: {environment}");
+            }
+            let collected = teller.collect_with_report().await?;
+            io::print_kvs(&collected.all, &collected.shadowed, &teller.config().policy);
+            io::print_skipped(&collected.skipped);
             Response::ok()
         }
-        Commands::Sh {} => {
-            let teller = load_teller(args.config.clone()).await?;
-            let out = teller.export(&export::Format::Shell).await?;
+        Commands::Sh {
+            allow_sensitive,
+            select,
+        } => {
+            let teller = load_selected(args, select).await?;
+            let out = teller
+                .export(&export::Format::Shell, allow_sensitive)
+                .await?;
             Response::ok_with_message(out)
         }
         Commands::Put {
@@ -311,7 +613,7 @@ pub async fn run(args: &Cli) -> eyre::Result<Response> {
                 .iter()
                 .map(|(k, v)| KV::from_kv(k, v))
                 .collect::<Vec<_>>();
-            let teller = load_teller(args.config.clone()).await?;
+            let teller = load_teller(args).await?;
             teller
                 .put(kvs.as_slice(), map_id.as_str(), providers.as_slice())
                 .await?;
@@ -322,7 +624,7 @@ pub async fn run(args: &Cli) -> eyre::Result<Response> {
             providers,
             keys,
         } => {
-            let teller = load_teller(args.config.clone()).await?;
+            let teller = load_teller(args).await?;
             teller
                 .delete(keys.as_slice(), &map_id, providers.as_slice())
                 .await?;
@@ -337,7 +639,7 @@ pub async fn run(args: &Cli) -> eyre::Result<Response> {
             // dotenv/map-id -> foo/map-id: copied 4 key(s).
             // dotenv/map-id -> f/map-id: copied 4 key(s).
             // copied 4 key(s) [in replace mode] from `dotenv:path-id` to `foo:path-id`, `bar:path-id`
-            let teller = load_teller(args.config.clone()).await?;
+            let teller = load_teller(args).await?;
             let (from_provider, from_map_id) = from.split_once('/').ok_or_else(|| {
                 eyre!(
                     "cannot parse '--from': '{}', did you format it as: '<provider name>/<map \
@@ -360,5 +662,115 @@ pub async fn run(args: &Cli) -> eyre::Result<Response> {
 
             Response::ok()
         }
+        Commands::Get {
+            location,
+            keys,
+            version,
+        } => {
+            let (provider, map_id) = parse_map_location(&location)?;
+            let teller = load_teller(args).await?;
+            let kvs = teller
+                .get(provider, map_id, version.as_deref())
+                .await?
+                .into_iter()
+                .filter(|kv| keys.is_empty() || keys.contains(&kv.key))
+                .collect::<Vec<_>>();
+            io::print_kvs(&kvs, &[], &teller.config().policy);
+            Response::ok()
+        }
+        Commands::History { location, key } => {
+            let (provider, map_id) = parse_map_location(&location)?;
+            let teller = load_teller(args).await?;
+            let versions = teller
+                .history(provider, map_id)
+                .await?
+                .into_iter()
+                // versions of a whole path cover every key in it
+                .filter(|v| key.is_none() || v.key.is_none() || v.key == key)
+                .collect::<Vec<_>>();
+            io::print_versions(&versions);
+            Response::ok()
+        }
+        Commands::Config(ConfigCommands::Show { resolved }) => {
+            if resolved {
+                print!("{}", serde_yaml::to_string(&load_config(args)?)?);
+            } else {
+                for layer in config_layers(args.config.clone())? {
+                    println!("{}", layer.display());
+                }
+            }
+            Response::ok()
+        }
+        Commands::Cache(CacheCommands::Clear { provider }) => {
+            let teller = load_teller(args).await?;
+            let cleared = teller.clear_cache(provider.as_deref())?;
+            if cleared.is_empty() {
+                return Response::ok_with_message(
+                    "nothing to clear: no provider declares a `cache`".to_string(),
+                );
+            }
+            Response::ok_with_message(format!("cleared cache of: {}", cleared.join(", ")))
+        }
+        Commands::Ls { provider, prefix } => {
+            let teller = load_teller(args).await?;
+            let entries = teller.list(&provider, &prefix).await?;
+            io::print_entries(&entries);
+            Response::ok()
+        }
+        Commands::Doctor { json, timeout } => {
+            // providers are built one by one here, a broken one must not stop the check
+            let config = load_config(args)?;
+            let probes = doctor::diagnose(&config, Duration::from_secs(timeout)).await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&probes)?);
+            } else {
+                io::print_probes(&probes);
+            }
+            if probes.iter().all(|p| p.passed(&config)) {
+                Response::ok()
+            } else {
+                Response::fail()
+            }
+        }
+        Commands::Check { json, select } => {
+            let teller = load_selected(args, select).await?;
+            let violations = teller.check().await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&violations)?);
+            } else {
+                for violation in &violations {
+                    println!("{violation}");
+                }
+            }
+            if !violations.is_empty() {
+                return Response::fail();
+            }
+            if json {
+                return Response::ok();
+            }
+            Response::ok_with_message(format!(
+                "{} key(s) match the schema",
+                teller.config().schema.len()
+            ))
+        }
+        Commands::Sync { replace, dry_run } => {
+            let teller = load_teller(args).await?;
+            let (verb, plan) = if dry_run {
+                ("would sync", teller.sync_plan()?)
+            } else {
+                ("synced", teller.sync(replace).await?)
+            };
+            if plan.is_empty() {
+                return Response::ok_with_message(
+                    "nothing to sync: no map declares a `sink`".to_string(),
+                );
+            }
+            Response::ok_with_message(
+                plan.iter()
+                    .map(|pair| format!("{verb} {pair}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+        }
     }
 }
@@ -1,8 +1,14 @@
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
 
+use comfy_table::{presets::NOTHING, Table};
 use eyre::Result;
 use fs_err::File;
-use teller_providers::config::KV;
+use teller_core::{
+    doctor::Probe,
+    policy::Policy,
+    teller::{origin, Shadowed, SkippedMap},
+};
+use teller_providers::config::{ListEntry, VersionInfo, KV};
 
 /// Read from a file or stdin
 ///
@@ -31,15 +37,107 @@ pub fn or_stdout(file: Option<String>) -> Result<Box<dyn Write>> {
     Ok(out)
 }
 
-pub fn print_kvs(kvs: &[KV]) {
+/// Print kvs with a value preview, marking the ones that shadow or are shadowed by the
+/// same key of other maps
+pub fn print_kvs(kvs: &[KV], shadowed: &[Shadowed], policy: &Policy) {
     for kv in kvs {
+        let shadows = shadowed
+            .iter()
+            .filter(|s| s.by == *kv)
+            .map(|s| origin(&s.kv))
+            .collect::<Vec<_>>();
+        let mark = shadowed.iter().find(|s| s.kv == *kv).map_or_else(
+            || {
+                if shadows.is_empty() {
+                    String::new()
+                } else {
+                    format!(" (shadows {})", shadows.join(", "))
+                }
+            },
+            |s| format!(" (shadowed by {})", origin(&s.by)),
+        );
         println!(
-            "[{}]: {} = {}***",
+            "[{}]: {} = {}***{}",
             kv.provider
                 .as_ref()
                 .map_or_else(|| "n/a".to_string(), |p| format!("{} ({})", p.name, p.kind)),
             kv.key,
-            kv.value.get(0..2).unwrap_or_default()
+            if policy.previewable(kv) {
+                kv.value.get(0..2).unwrap_or_default()
+            } else {
+                ""
+            },
+            mark
+        );
+    }
+}
+
+pub fn print_skipped(skipped: &[SkippedMap]) {
+    for map in skipped {
+        eprintln!(
+            "skipped optional map '{}' on '{}': {}",
+            map.map_id, map.provider, map.error
         );
     }
 }
+
+/// Print the entries of a provider listing, one per line
+pub fn print_entries(entries: &[ListEntry]) {
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+    for entry in entries {
+        match entry {
+            ListEntry::Path(path) => table.add_row(vec!["path", path]),
+            ListEntry::Key(key) => table.add_row(vec!["key", key]),
+        };
+    }
+    println!("{table}");
+}
+
+/// Print versions, newest first as given by the provider
+pub fn print_versions(versions: &[VersionInfo]) {
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+    for v in versions {
+        let status = if v.deleted {
+            "deleted"
+        } else if v.current {
+            "current"
+        } else {
+            ""
+        };
+        let mut row = vec![
+            v.version.as_str(),
+            v.created_at.as_deref().unwrap_or("n/a"),
+            status,
+        ];
+        if let Some(key) = &v.key {
+            row.insert(0, key);
+        }
+        table.add_row(row);
+    }
+    println!("{table}");
+}
+
+/// Print a row per probed map, and the errors behind failed probes
+pub fn print_probes(probes: &[Probe]) {
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+    table.set_header(vec!["PROVIDER", "MAP", "PATH", "STATUS", "KEYS", "LATENCY"]);
+    for p in probes {
+        table.add_row(vec![
+            p.provider.clone(),
+            p.map_id.clone(),
+            p.path.clone(),
+            p.status.to_string(),
+            p.keys.to_string(),
+            format!("{}ms", p.latency_ms),
+        ]);
+    }
+    println!("{table}");
+    for p in probes {
+        if let Some(error) = &p.error {
+            eprintln!("{}/{}: {error}", p.provider, p.map_id);
+        }
+    }
+}
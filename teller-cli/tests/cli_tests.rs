@@ -9,6 +9,12 @@ fn prep_data_for_mutating_tests() {
     .expect("writing a fixture file");
     fs::write("tests/cmd/copy.in/target.env", "TARGET_ONLY=true\n")
         .expect("writing a fixture file");
+    fs::write("tests/cmd/sync.in/local.env", "LOCAL_ONLY=true\n").expect("writing a fixture file");
+    fs::write(
+        "tests/cmd/formats.in/db.env",
+        "CONFIG='{\"DB_PASS\":\"1234\",\"DB_NAME\":\"FOO\",\"replica\":{\"host\":\"db2\"}}'\n",
+    )
+    .expect("writing a fixture file");
 }
 #[test]
 fn cli_tests() {
@@ -17,6 +23,7 @@ fn cli_tests() {
 
     let c = trycmd::TestCases::new();
     c.case("tests/cmd/*.trycmd");
+    c.case("tests/cmd/*.toml");
     #[cfg(windows)]
     c.skip("tests/cmd/run.trycmd");
 
@@ -81,10 +81,10 @@ HELLO=world
             &vars,
         )
         .unwrap();
-        let registry = Registry::new(&config.providers).await.unwrap();
+        let registry = Registry::new(&config.providers).unwrap();
 
         // (1) start: put on hashi
-        let hashi = registry.get("hashi_1").unwrap();
+        let hashi = registry.get("hashi_1").await.unwrap().unwrap();
         let hashi_pm0 = &config.providers.get("hashi_1").unwrap().maps[0];
         hashi
             .put(
@@ -120,7 +120,7 @@ HELLO=world
         // (2) push results into secretsmanager
         let kvs = res.unwrap();
 
-        let smgr = registry.get("sm_1").unwrap();
+        let smgr = registry.get("sm_1").await.unwrap().unwrap();
         let smgr_pm0 = &config.providers.get("sm_1").unwrap().maps[0];
         smgr.put(smgr_pm0, &kvs[..]).await.unwrap();
         let res = smgr.get(smgr_pm0).await;
@@ -130,7 +130,7 @@ HELLO=world
         // check that in snapshots (USER -> USER_NAME, and drops the pass)
         let kvs = res.unwrap();
 
-        let ssm = registry.get("ssm_1").unwrap();
+        let ssm = registry.get("ssm_1").await.unwrap().unwrap();
         let ssm_pm0 = &config.providers.get("ssm_1").unwrap().maps[0];
         ssm.put(ssm_pm0, &kvs[..]).await.unwrap();
         let res = ssm.get(ssm_pm0).await;
@@ -139,7 +139,7 @@ HELLO=world
         // (4) lastly, write it into dotenv file, read it back, and snapshot it
         let kvs = res.unwrap();
 
-        let dot = registry.get("dot_1").unwrap();
+        let dot = registry.get("dot_1").await.unwrap().unwrap();
         let dot_pm0 = &config.providers.get("dot_1").unwrap().maps[0];
         dot.put(dot_pm0, &kvs[..]).await.unwrap();
         let res = dot.get(dot_pm0).await;
@@ -11,11 +11,72 @@ use teller_providers::config::{PathMap, ProviderCfg, KV};
 use teller_providers::providers::ProviderKind;
 use tera::{Context, Tera};
 
-use crate::Result;
+use crate::{
+    computed::ComputedMap, exec::ExecConfig, policy::Policy, schema::KeyRule, Error, Result,
+};
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Config {
     pub providers: BTreeMap<String, ProviderCfg>,
+    /// Maximum number of maps fetched concurrently while collecting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Which errors cause an `optional` map to be skipped while collecting
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub optional_policy: OptionalPolicy,
+    /// Sensitivity based redaction, export and display rules
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub policy: Policy,
+    /// Named profiles applied on top of `providers`, see [`Config::with_environment`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environments: BTreeMap<String, Environment>,
+    /// The profile applied to this configuration, if any
+    #[serde(skip)]
+    pub environment: Option<String>,
+    /// Keys built from other keys after collecting, see [`crate::computed`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub computed: Vec<ComputedMap>,
+    /// Keys the collected environment must provide, see [`crate::schema`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub schema: BTreeMap<String, KeyRule>,
+    /// The environment commands inherit, see [`ExecConfig`]
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub exec: ExecConfig,
+}
+
+/// Overrides for a named environment (e.g. `dev`, `prod`)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Environment {
+    /// Provider settings merged over the base providers by name. Maps are matched by
+    /// `id`: a matching map is merged field by field, other maps are added.
+    #[serde(default)]
+    pub providers: BTreeMap<String, serde_yaml::Mapping>,
+}
+
+/// Decides which provider errors are tolerated for maps marked as `optional`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub enum OptionalPolicy {
+    /// Skip an optional map only when its path is not found
+    #[default]
+    #[serde(rename = "not_found")]
+    NotFound,
+    /// Skip an optional map on any provider error
+    #[serde(rename = "any_error")]
+    AnyError,
+}
+
+impl OptionalPolicy {
+    #[must_use]
+    pub const fn skips(&self, err: &teller_providers::Error) -> bool {
+        match self {
+            Self::NotFound => matches!(err, teller_providers::Error::NotFound { .. }),
+            Self::AnyError => true,
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -27,9 +88,8 @@ fn apply_eqeq(config: &mut Config) {
     config.providers.iter_mut().for_each(|(_name, provider)| {
         provider.maps.iter_mut().for_each(|pm| {
             pm.keys.iter_mut().for_each(|(k, v)| {
-                // THINK: replace with:
-                // 1. templating: {{id}} (identity), {{snake_case}} (snake case it)
-                // 2. other symbols: == id, ^^ capitalize, snake case __ lower snake case
+                // case conversion, prefixes and renames are `key_transform`, `key_prefix`,
+                // `key_suffix` and `key_rename` on the map
                 if v == "==" {
                     v.clone_from(k);
                 }
@@ -38,19 +98,201 @@ fn apply_eqeq(config: &mut Config) {
     });
 }
 
+/// Merge `overlay` into `base`: mappings merge by key, anything else is replaced
+fn merge(base: &mut serde_yaml::Value, overlay: &serde_yaml::Value) {
+    match (base, overlay) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            for (k, v) in overlay {
+                match base.get_mut(k) {
+                    Some(existing) => merge(existing, v),
+                    None => {
+                        base.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+        }
+        (base, overlay) => base.clone_from(overlay),
+    }
+}
+
+/// Merge overlay maps into base maps by their `id`
+fn merge_maps(base: &mut serde_yaml::Value, overlay: &serde_yaml::Value) {
+    match (base, overlay) {
+        (serde_yaml::Value::Sequence(base), serde_yaml::Value::Sequence(overlay)) => {
+            for map in overlay {
+                match base.iter_mut().find(|b| b.get("id") == map.get("id")) {
+                    Some(existing) => merge(existing, map),
+                    None => base.push(map.clone()),
+                }
+            }
+        }
+        (base, overlay) => base.clone_from(overlay),
+    }
+}
+
+/// Merge overlay providers into base providers by name. Provider settings merge key by
+/// key, maps merge by `id`.
+fn merge_providers(base: &mut serde_yaml::Value, overlay: &serde_yaml::Value) {
+    let (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) = (base, overlay)
+    else {
+        return;
+    };
+    for (name, provider) in overlay {
+        let Some(existing) = base.get_mut(name) else {
+            base.insert(name.clone(), provider.clone());
+            continue;
+        };
+        match (existing, provider) {
+            (serde_yaml::Value::Mapping(existing), serde_yaml::Value::Mapping(provider)) => {
+                for (k, v) in provider {
+                    match existing.get_mut(k) {
+                        Some(field) if k.as_str() == Some("maps") => merge_maps(field, v),
+                        Some(field) => merge(field, v),
+                        None => {
+                            existing.insert(k.clone(), v.clone());
+                        }
+                    }
+                }
+            }
+            (existing, provider) => existing.clone_from(provider),
+        }
+    }
+}
+
+/// Merge a whole overlay configuration into `base`: providers as in [`merge_providers`],
+/// environments by name (with their providers merged the same way), other settings key
+/// by key
+fn merge_config(base: &mut serde_yaml::Value, overlay: &serde_yaml::Value) {
+    let (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) = (base, overlay)
+    else {
+        return;
+    };
+    for (k, v) in overlay {
+        let Some(existing) = base.get_mut(k) else {
+            base.insert(k.clone(), v.clone());
+            continue;
+        };
+        match k.as_str() {
+            Some("providers") => merge_providers(existing, v),
+            Some("environments") => {
+                let (serde_yaml::Value::Mapping(existing), serde_yaml::Value::Mapping(v)) =
+                    (existing, v)
+                else {
+                    continue;
+                };
+                for (name, environment) in v {
+                    match existing.get_mut(name) {
+                        Some(current) => merge_config(current, environment),
+                        None => {
+                            existing.insert(name.clone(), environment.clone());
+                        }
+                    }
+                }
+            }
+            _ => merge(existing, v),
+        }
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Render and parse one configuration file, and merge it over the files it `include`s.
+/// `stack` holds the files being loaded, to catch include cycles.
+fn load_layer(
+    path: &Path,
+    vars: &HashMap<String, String>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<serde_yaml::Value> {
+    let canonical = fs::canonicalize(path)?;
+    if stack.contains(&canonical) {
+        return Err(Error::Message(format!(
+            "include cycle: {} -> {}",
+            stack
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> "),
+            canonical.display()
+        )));
+    }
+    stack.push(canonical);
+    let text = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let layer = resolve_includes(&text, dir, vars, stack);
+    stack.pop();
+    layer
+}
+
+fn resolve_includes(
+    text: &str,
+    dir: &Path,
+    vars: &HashMap<String, String>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<serde_yaml::Value> {
+    let rendered_text = Tera::one_off(text, &Context::from_serialize(vars)?, false)?;
+    let mut value: serde_yaml::Value = serde_yaml::from_str(&rendered_text)?;
+    let includes: Vec<String> = match value.as_mapping_mut().and_then(|m| m.remove("include")) {
+        Some(serde_yaml::Value::String(include)) => vec![include],
+        Some(includes) => serde_yaml::from_value(includes)?,
+        None => vec![],
+    };
+
+    let mut merged = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+    for include in includes {
+        let layer = load_layer(&dir.join(expand_home(&include)), vars, stack)?;
+        merge_config(&mut merged, &layer);
+    }
+    merge_config(&mut merged, &value);
+    Ok(merged)
+}
+
+fn from_value(value: serde_yaml::Value) -> Result<Config> {
+    let mut config: Config = serde_yaml::from_value(value)?;
+    apply_eqeq(&mut config);
+    Ok(config)
+}
+
 impl Config {
-    /// Config from text
+    /// Apply the environment profile `name` on top of this configuration
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such environment, or the
+    /// merged provider configuration is invalid
+    pub fn with_environment(mut self, name: &str) -> Result<Self> {
+        let environment = self.environments.get(name).ok_or_else(|| {
+            Error::Message(format!(
+                "cannot find environment '{name}', expected one of: {}",
+                self.environments
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })?;
+        let mut providers = serde_yaml::to_value(&self.providers)?;
+        merge_providers(
+            &mut providers,
+            &serde_yaml::to_value(&environment.providers)?,
+        );
+        self.providers = serde_yaml::from_value(providers)?;
+        apply_eqeq(&mut self);
+        self.environment = Some(name.to_string());
+        Ok(self)
+    }
+
+    /// Config from text, `include`d files are relative to the current directory
     ///
     /// # Errors
     ///
     /// This function will return an error if serialization fails
     pub fn with_vars(text: &str, vars: &HashMap<String, String>) -> Result<Self> {
-        let rendered_text = Tera::one_off(text, &Context::from_serialize(vars)?, false)?;
-        let mut config: Self = serde_yaml::from_str(&rendered_text)?;
-
-        apply_eqeq(&mut config);
-
-        Ok(config)
+        from_value(resolve_includes(text, Path::new("."), vars, &mut vec![])?)
     }
 
     /// Config from text
@@ -62,13 +304,28 @@ impl Config {
         Self::with_vars(text, &HashMap::new())
     }
 
-    /// Config from file
+    /// Config from file, merged over the files it `include`s
     ///
     /// # Errors
     ///
     /// This function will return an error if IO fails
     pub fn from_path(path: &Path) -> Result<Self> {
-        Self::from_text(&fs::read_to_string(path)?)
+        Self::from_layers(&[path.to_path_buf()])
+    }
+
+    /// Config from layered files, each one merged over the ones before it: providers by
+    /// name, their settings key by key and their maps by `id`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if IO fails, or a file includes itself
+    pub fn from_layers(paths: &[PathBuf]) -> Result<Self> {
+        let mut merged = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+        for path in paths {
+            let layer = load_layer(path, &HashMap::new(), &mut vec![])?;
+            merge_config(&mut merged, &layer);
+        }
+        from_value(merged)
     }
 
     /// Create configuration template file
@@ -91,7 +348,10 @@ impl Config {
             })
             .collect();
 
-        let config = Self { providers: res };
+        let config = Self {
+            providers: res,
+            ..Self::default()
+        };
 
         let a: String = serde_yaml::to_string(&config)?;
         Ok(a)
@@ -146,4 +406,96 @@ mod tests {
         let config = Config::render_template(&data).unwrap();
         assert_yaml_snapshot!(config);
     }
+
+    const ENVIRONMENTS: &str = r"
+providers:
+  vault:
+    kind: inmem
+    options:
+      dev/app: { TOKEN: dev }
+      prod/app: { TOKEN: prod, DB: db }
+    maps:
+      - id: app
+        path: dev/app
+        keys:
+          TOKEN: ==
+environments:
+  prod:
+    providers:
+      vault:
+        maps:
+          - id: app
+            path: prod/app
+            keys:
+              DB: DATABASE_URL
+          - id: extra
+            path: prod/extra
+            optional: true
+";
+
+    #[test]
+    fn applies_environments() {
+        let config = Config::from_text(ENVIRONMENTS).unwrap();
+        assert_eq!(config.environment, None);
+
+        let prod = config.clone().with_environment("prod").unwrap();
+        assert_eq!(prod.environment.as_deref(), Some("prod"));
+        let vault = &prod.providers["vault"];
+        assert_eq!(vault.kind, ProviderKind::Inmem);
+        assert_eq!(vault.options, config.providers["vault"].options);
+        assert_eq!(vault.maps.len(), 2);
+        assert_eq!(vault.maps[0].path, "prod/app");
+        assert_eq!(
+            vault.maps[0].keys,
+            BTreeMap::from([
+                ("DB".to_string(), "DATABASE_URL".to_string()),
+                ("TOKEN".to_string(), "TOKEN".to_string()),
+            ])
+        );
+        assert!(vault.maps[1].optional);
+
+        let err = config.with_environment("staging").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot find environment 'staging', expected one of: prod"
+        );
+    }
+
+    #[test]
+    fn merges_includes_and_layers() {
+        let config = Config::from_layers(&[
+            PathBuf::from("fixtures/layers/app.yml"),
+            PathBuf::from("fixtures/layers/local.yml"),
+        ])
+        .unwrap();
+        let vault = &config.providers["vault"];
+        assert_eq!(vault.kind, ProviderKind::Etcd);
+        assert_eq!(
+            vault.options,
+            Some(serde_json::json!({
+                "address": "https://etcd.example.com",
+                "namespace": "team",
+            }))
+        );
+        assert_eq!(
+            vault
+                .maps
+                .iter()
+                .map(|pm| (pm.id.as_str(), pm.path.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("shared", "secret/shared"),
+                ("app", "secret/team/app"),
+                ("extra", "secret/team/extra"),
+            ]
+        );
+        assert_eq!(vault.maps[1].keys["TOKEN"], "TOKEN");
+        assert_eq!(config.providers["dot"].maps[0].path, ".env.local");
+    }
+
+    #[test]
+    fn include_cycles_fail() {
+        let err = Config::from_path(Path::new("fixtures/layers/cycle-a.yml")).unwrap_err();
+        assert!(err.to_string().starts_with("include cycle: "));
+    }
 }
@@ -0,0 +1,309 @@
+//! Health checks for configured providers
+//!
+//! Every provider is built on its own, so one broken provider does not hide the state of the
+//! others, and every map is probed with a `get` whose failure is reported instead of returned.
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use futures::stream::{self, StreamExt};
+use serde_derive::Serialize;
+use teller_providers::{
+    config::{PathMap, ProviderCfg},
+    registry::RegistryBuilder,
+    Error,
+};
+
+use crate::{
+    config::{Config, OptionalPolicy},
+    teller::DEFAULT_CONCURRENCY,
+};
+
+/// How long a provider gets to load, and then to answer each probe, when not set otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Ok,
+    NotFound,
+    AuthError,
+    Timeout,
+    Error,
+}
+
+impl Status {
+    fn of(err: &Error) -> Self {
+        match err {
+            Error::NotFound { .. } => Self::NotFound,
+            Error::Timeout { .. } => Self::Timeout,
+            Error::IO(err) if err.kind() == std::io::ErrorKind::TimedOut => Self::Timeout,
+            err if err.is_auth_error() => Self::AuthError,
+            _ => Self::Error,
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "ok",
+            Self::NotFound => "not-found",
+            Self::AuthError => "auth-error",
+            Self::Timeout => "timeout",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// The outcome of probing one map
+#[derive(Serialize, Debug, Clone)]
+pub struct Probe {
+    pub provider: String,
+    pub map_id: String,
+    pub path: String,
+    pub optional: bool,
+    pub status: Status,
+    /// Number of keys the map resolved to
+    pub keys: usize,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Probe {
+    /// A probe passes when it resolved, or when it is an optional map that would be
+    /// skipped with this error anyway
+    #[must_use]
+    pub fn passed(&self, config: &Config) -> bool {
+        self.status == Status::Ok
+            || (self.optional
+                && (config.optional_policy == OptionalPolicy::AnyError
+                    || self.status == Status::NotFound))
+    }
+}
+
+/// Build every provider and probe all of its maps
+pub async fn diagnose(config: &Config, timeout: Duration) -> Vec<Probe> {
+    diagnose_with_registry(config, timeout, RegistryBuilder::new).await
+}
+
+/// Like [`diagnose`], building each provider with a fresh registry from `registry`, so
+/// custom providers and kinds can be checked too
+pub async fn diagnose_with_registry<F>(
+    config: &Config,
+    timeout: Duration,
+    registry: F,
+) -> Vec<Probe>
+where
+    F: Fn() -> RegistryBuilder + Sync,
+{
+    let checks = config
+        .providers
+        .iter()
+        .map(|(name, providercfg)| check(name, providercfg, timeout, &registry));
+    stream::iter(checks)
+        .buffered(config.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+async fn check<F>(
+    name: &str,
+    providercfg: &ProviderCfg,
+    timeout: Duration,
+    registry: &F,
+) -> Vec<Probe>
+where
+    F: Fn() -> RegistryBuilder,
+{
+    let started = Instant::now();
+    // without a provider no map resolves, each one carries the loading error
+    let failed = |err: Error| -> Vec<Probe> {
+        let failed = (Status::of(&err), err.to_string());
+        providercfg
+            .maps
+            .iter()
+            .map(|pm| probe(name, pm, started, Err(failed.clone())))
+            .collect()
+    };
+    // probe the backend itself, a cache would report entries it kept from earlier runs
+    let uncached = ProviderCfg {
+        cache: None,
+        ..providercfg.clone()
+    };
+    let single = BTreeMap::from([(name.to_string(), uncached)]);
+    let registry = match registry().build(&single) {
+        Ok(registry) => registry,
+        Err(err) => return failed(err),
+    };
+    let provider = match with_timeout(name, timeout, registry.get(name)).await {
+        Ok(Some(provider)) => provider,
+        Ok(None) => return vec![],
+        Err(err) => return failed(err),
+    };
+
+    let mut probes = Vec::new();
+    for pm in &providercfg.maps {
+        let started = Instant::now();
+        let res = with_timeout(&pm.path, timeout, provider.get(pm))
+            .await
+            .map(|kvs| kvs.len())
+            .map_err(|err| (Status::of(&err), err.to_string()));
+        probes.push(probe(name, pm, started, res));
+    }
+    probes
+}
+
+fn probe(
+    name: &str,
+    pm: &PathMap,
+    started: Instant,
+    res: Result<usize, (Status, String)>,
+) -> Probe {
+    let latency_ms = started.elapsed().as_millis();
+    let (status, keys, error) = match res {
+        Ok(keys) => (Status::Ok, keys, None),
+        Err((status, error)) => (status, 0, Some(error)),
+    };
+    Probe {
+        provider: name.to_string(),
+        map_id: pm.id.clone(),
+        path: pm.path.clone(),
+        optional: pm.optional,
+        status,
+        keys,
+        latency_ms,
+        error,
+    }
+}
+
+async fn with_timeout<T>(
+    path: &str,
+    timeout: Duration,
+    fut: impl std::future::Future<Output = teller_providers::Result<T>> + Send,
+) -> teller_providers::Result<T> {
+    tokio::time::timeout(timeout, fut)
+        .await
+        .unwrap_or_else(|_| {
+            Err(Error::Timeout {
+                path: path.to_string(),
+                msg: format!("no response after {}ms", timeout.as_millis()),
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r"
+providers:
+  mem:
+    kind: inmem
+    options:
+      one: { A: '1', B: '2' }
+    maps:
+      - id: one
+        path: one
+      - id: missing
+        path: missing
+      - id: extra
+        path: extra
+        optional: true
+  broken:
+    kind: acme
+    maps:
+      - id: app
+        path: app
+";
+
+    #[tokio::test]
+    async fn probes_every_map_of_every_provider() {
+        let config = Config::from_text(CONFIG).unwrap();
+        let probes = diagnose(&config, DEFAULT_TIMEOUT).await;
+        assert_eq!(
+            probes
+                .iter()
+                .map(|p| (p.provider.as_str(), p.map_id.as_str(), p.status, p.keys))
+                .collect::<Vec<_>>(),
+            vec![
+                ("broken", "app", Status::Error, 0),
+                ("mem", "one", Status::Ok, 2),
+                ("mem", "missing", Status::NotFound, 0),
+                ("mem", "extra", Status::NotFound, 0),
+            ]
+        );
+        assert_eq!(
+            probes.iter().map(|p| p.passed(&config)).collect::<Vec<_>>(),
+            vec![false, true, false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn builds_custom_kinds_with_the_given_registry() {
+        let config = Config::from_text(CONFIG).unwrap();
+        let probes = diagnose_with_registry(&config, DEFAULT_TIMEOUT, || {
+            RegistryBuilder::new().with_factory("acme", |name, _| {
+                Ok(Box::new(teller_providers::providers::inmem::Inmem::new(
+                    name,
+                    Some(serde_json::json!({ "app": { "C": "3" } })),
+                )?))
+            })
+        })
+        .await;
+        assert_eq!(probes[0].status, Status::Ok);
+        assert_eq!(probes[0].keys, 1);
+    }
+
+    #[tokio::test]
+    async fn probes_past_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join("app.env");
+        std::fs::write(&env, "A=1\n").unwrap();
+        let config = Config::from_text(&format!(
+            "
+providers:
+  dot:
+    kind: dotenv
+    cache:
+      ttl: 1h
+      dir: {}
+    maps:
+      - id: app
+        path: {}
+",
+            dir.path().join("cache").display(),
+            env.display()
+        ))
+        .unwrap();
+        // a run fills the cache, then the file goes away
+        let registry = teller_providers::registry::Registry::new(&config.providers).unwrap();
+        let provider = registry.get("dot").await.unwrap().unwrap();
+        provider
+            .get(&config.providers["dot"].maps[0])
+            .await
+            .unwrap();
+        std::fs::remove_file(&env).unwrap();
+
+        let probes = diagnose(&config, DEFAULT_TIMEOUT).await;
+        assert_ne!(probes[0].status, Status::Ok);
+    }
+
+    #[test]
+    fn classifies_errors() {
+        let status = |msg: &str| {
+            Status::of(&Error::GetError {
+                path: "app".to_string(),
+                msg: msg.to_string(),
+            })
+        };
+        assert_eq!(status("403 Forbidden"), Status::AuthError);
+        assert_eq!(status("invalid token"), Status::AuthError);
+        assert_eq!(status("malformed secret"), Status::Error);
+    }
+}
@@ -1,13 +1,89 @@
-use std::{collections::HashMap, path::Path, process::Output};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Output},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use teller_providers::config::KV;
 
 // use crate::{Error, Result};
 // use teller_providers::errors::{Error, Result};
-use crate::{Error, Result};
+use crate::{redact::Redactor, Error, Result};
 pub struct Opts<'a> {
     pub pwd: &'a Path,
     pub capture: bool,
     pub sh: bool,
     pub reset_env: bool,
+    /// Pipe stdout and stderr through the redactor, line by line, when running with [`run`]
+    pub redact: bool,
+    /// Which inherited variables the command sees
+    pub env: ExecConfig,
+    /// Where [`run`] writes keys delivered as files, a memory backed temporary directory
+    /// when not given
+    pub files_dir: Option<&'a Path>,
+    /// Keep collecting while the command runs, see [`crate::teller::Teller::run`]
+    pub watch: Option<Watch>,
+}
+
+/// Re-collecting while a command runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watch {
+    /// The longest time between two collections, providers able to notify changes may
+    /// trigger one sooner
+    pub interval: Duration,
+    pub on_change: OnChange,
+}
+
+/// What happens to a running command when collected values change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnChange {
+    /// Stop the command and start it again with the new values
+    Restart,
+    /// Rewrite its files and send it a signal, e.g. `signal:HUP`. Its environment can't
+    /// change, so it should read values from files to pick them up.
+    Signal(i32),
+}
+
+impl std::str::FromStr for OnChange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "restart" => Ok(Self::Restart),
+            Some(("signal", name)) => signal_number(name)
+                .map(Self::Signal)
+                .ok_or_else(|| Error::Message(format!("unknown signal '{name}'"))),
+            _ => Err(Error::Message(format!(
+                "unknown change action '{s}', expected 'restart' or 'signal:<NAME>'"
+            ))),
+        }
+    }
+}
+
+/// A signal by name, with or without `SIG`, or by number
+#[cfg(unix)]
+fn signal_number(name: &str) -> Option<i32> {
+    let name = name.to_uppercase();
+    Some(match name.strip_prefix("SIG").unwrap_or(&name) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "TERM" => libc::SIGTERM,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        other => return other.parse().ok(),
+    })
+}
+
+#[cfg(windows)]
+fn signal_number(_name: &str) -> Option<i32> {
+    None
 }
 
 const ENV_OK: &[&str] = &[
@@ -25,12 +101,589 @@ const ENV_OK: &[&str] = &[
     "LOGNAME",
 ];
 
+const fn default_true() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_true(b: &bool) -> bool {
+    *b
+}
+
+/// The `exec` section: which variables of teller's own environment a command inherits
+///
+/// ## Example configuration
+///
+/// ```yaml
+/// exec:
+///   keep: [KUBECONFIG, AWS_PROFILE]
+///   keep_patterns: ['XDG_*', '*_PROXY']
+///   deny: ['*_TOKEN']
+/// ```
+///
+/// `keep` and `keep_patterns` extend the allowlist used when resetting the environment,
+/// or replace it with `keep_defaults: false`. `deny` always applies, reset or not, and
+/// wins over the allowlist. Patterns match whole names, with `*` matching any run of
+/// characters. Collected keys are never filtered.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExecConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep_patterns: Vec<String>,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub keep_defaults: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl Default for ExecConfig {
+    fn default() -> Self {
+        Self {
+            keep: vec![],
+            keep_patterns: vec![],
+            keep_defaults: true,
+            deny: vec![],
+        }
+    }
+}
+
+impl ExecConfig {
+    /// Add the rules of `other` to these
+    pub fn extend(&mut self, other: &Self) {
+        self.keep.extend(other.keep.iter().cloned());
+        self.keep_patterns
+            .extend(other.keep_patterns.iter().cloned());
+        self.keep_defaults &= other.keep_defaults;
+        self.deny.extend(other.deny.iter().cloned());
+    }
+
+    fn keeps(&self, name: &str) -> bool {
+        (self.keep_defaults && ENV_OK.contains(&name))
+            || self.keep.iter().any(|k| k == name)
+            || self.keep_patterns.iter().any(|p| pattern_matches(p, name))
+    }
+
+    fn denies(&self, name: &str) -> bool {
+        self.deny.iter().any(|p| pattern_matches(p, name))
+    }
+
+    /// The variables of `vars` a command inherits, `reset` limiting them to the allowlist
+    fn inherit(
+        &self,
+        vars: impl Iterator<Item = (String, String)>,
+        reset: bool,
+    ) -> HashMap<String, String> {
+        vars.filter(|(k, _)| (!reset || self.keeps(k)) && !self.denies(k))
+            .collect()
+    }
+}
+
+/// Match a whole name against a pattern where `*` stands for any run of characters
+fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        // no `*` at all
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Secrets written to files for a command, in a private directory removed when dropped
+pub struct SecretFiles {
+    dir: tempfile::TempDir,
+}
+
+impl SecretFiles {
+    /// Write each of `kvs` to a file named after its key, readable only by the current user,
+    /// in a new private directory under `base`. Without a `base`, `/dev/shm` is used when
+    /// available so values never reach a disk. Returns the `KEY_FILE` variables pointing to
+    /// the files.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a key is not a valid file name, or writing fails
+    pub fn write(base: Option<&Path>, kvs: &[KV]) -> Result<(Self, Vec<(String, String)>)> {
+        let base = base.map_or_else(
+            || {
+                let shm = Path::new("/dev/shm");
+                if shm.is_dir() {
+                    shm.to_path_buf()
+                } else {
+                    std::env::temp_dir()
+                }
+            },
+            Path::to_path_buf,
+        );
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("teller-");
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+        let files = Self {
+            dir: builder.tempdir_in(&base)?,
+        };
+        let vars = files.put(kvs)?;
+        Ok((files, vars))
+    }
+
+    /// Write or replace the files of `kvs`. A file is replaced in one step, so readers see
+    /// either the old or the new value.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a key is not a valid file name, or writing fails
+    pub fn put(&self, kvs: &[KV]) -> Result<Vec<(String, String)>> {
+        let mut vars = Vec::new();
+        for kv in kvs {
+            if kv.key.is_empty() || kv.key.contains(['/', '\\']) || kv.key.starts_with('.') {
+                return Err(Error::Message(format!(
+                    "cannot deliver key '{}' as a file",
+                    kv.key
+                )));
+            }
+            let path = self.dir.path().join(&kv.key);
+            // keys never start with a dot, so this can't be another key's file
+            let staged = self.dir.path().join(format!(".{}", kv.key));
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(&staged)?.write_all(kv.value.as_bytes())?;
+            std::fs::rename(&staged, &path)?;
+            vars.push((
+                format!("{}_FILE", kv.key),
+                path.to_string_lossy().to_string(),
+            ));
+        }
+        Ok(vars)
+    }
+
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().to_path_buf()
+    }
+}
+
 /// Run a command
 ///
 /// # Errors
 ///
 /// This function will return an error if running command fails
 pub fn cmd(cmdstr: &str, env_kvs: &[(String, String)], opts: &Opts<'_>) -> Result<Output> {
+    Ok(expression(cmdstr, env_kvs, opts)?.run()?)
+}
+
+/// Run a command to completion with `kvs` in its environment and `files` delivered as
+/// [`SecretFiles`], forwarding SIGINT, SIGTERM and SIGHUP to it while it runs. Unlike [`cmd`],
+/// a command that fails is not an error: its status is in the output, see [`exit_code`].
+/// The files are removed once it exits. A Ctrl-C at the terminal already reaches the command,
+/// so SIGINT is only forwarded when teller does not run in the foreground.
+///
+/// In redaction mode, stdout and stderr each go through their own pipe and `redactor`
+/// before reaching ours, so they stay separate. Stdin is left attached, so interactive
+/// input keeps working, but the command no longer sees a terminal on its output. Output is
+/// redacted line by line, a prompt without a newline shows once the command pauses.
+///
+/// # Errors
+///
+/// This function will return an error if the command cannot be started
+pub async fn run(
+    cmdstr: &str,
+    kvs: &[KV],
+    files: &[KV],
+    redactor: &Redactor,
+    opts: &Opts<'_>,
+) -> Result<Output> {
+    start(cmdstr, kvs, files, &[], redactor, opts)?.wait().await
+}
+
+/// Start a command the way [`run`] does, without waiting for it. `shadowed` values are
+/// not handed to the command, but redacted from its output like the others.
+///
+/// # Errors
+///
+/// This function will return an error if the command cannot be started, or if a file's
+/// `KEY_FILE` variable has the name of a key in `kvs`
+pub fn start(
+    cmdstr: &str,
+    kvs: &[KV],
+    files: &[KV],
+    shadowed: &[KV],
+    redactor: &Redactor,
+    opts: &Opts<'_>,
+) -> Result<Running> {
+    if let Some((file, kv)) = files.iter().find_map(|file| {
+        let var = format!("{}_FILE", file.key);
+        kvs.iter().find(|kv| kv.key == var).map(|kv| (file, kv))
+    }) {
+        return Err(Error::Message(format!(
+            "key '{}' is delivered as a file through '{}', which is also a key of its own",
+            file.key, kv.key
+        )));
+    }
+    let mut env_kvs = kvs
+        .iter()
+        .map(|kv| (kv.key.clone(), kv.value.clone()))
+        .collect::<Vec<_>>();
+    let secret_files = if files.is_empty() {
+        None
+    } else {
+        let (secret_files, vars) = SecretFiles::write(opts.files_dir, files)?;
+        env_kvs.extend(vars);
+        Some(secret_files)
+    };
+    // file values are still secrets if the command prints them, and so are shadowed ones it
+    // may read elsewhere
+    let secrets = Arc::new(RwLock::new([kvs, files, shadowed].concat()));
+    let mut expr = expression(cmdstr, &env_kvs, opts)?.unchecked();
+    let mut pipes = Vec::new();
+    if opts.redact {
+        let (stdout, stdout_writer) = os_pipe::pipe()?;
+        let (stderr, stderr_writer) = os_pipe::pipe()?;
+        expr = expr.stdout_file(stdout_writer).stderr_file(stderr_writer);
+        pipes.push(redact_pipe(stdout, io::stdout, redactor, secrets.clone()));
+        pipes.push(redact_pipe(stderr, io::stderr, redactor, secrets.clone()));
+    }
+    let handle = Arc::new(expr.start()?);
+    // our ends of the pipes' write side must close for readers to see the end
+    drop(expr);
+    let waiting = {
+        let handle = handle.clone();
+        tokio::task::spawn_blocking(move || handle.wait().cloned())
+    };
+    Ok(Running {
+        handle,
+        waiting,
+        pipes,
+        secrets,
+        files: secret_files,
+    })
+}
+
+/// How long a command gets to exit after SIGTERM when stopped, before it is killed
+const STOP_GRACE: Duration = Duration::from_secs(10);
+
+/// How long the redacted output of a command that exited is drained for. A process it left
+/// in the background may hold its output open well beyond that.
+const DRAIN_GRACE: Duration = Duration::from_secs(1);
+
+/// How long redaction waits for the rest of a line before writing what it has
+const PARTIAL_LINE_IDLE: Duration = Duration::from_millis(100);
+
+/// A command started with [`start`]
+pub struct Running {
+    handle: Arc<duct::Handle>,
+    waiting: tokio::task::JoinHandle<io::Result<Output>>,
+    pipes: Vec<tokio::sync::oneshot::Receiver<io::Result<()>>>,
+    secrets: Arc<RwLock<Vec<KV>>>,
+    files: Option<SecretFiles>,
+}
+
+/// What ended [`Running::wait_until`]
+pub enum Waited<T> {
+    /// The command exited, [`Running::finish`] is left to do
+    Exited(Output),
+    /// The future given completed first, the command still runs
+    Until(T),
+}
+
+impl Running {
+    /// Wait for the command to exit, forwarding SIGINT, SIGTERM and SIGHUP to it
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if waiting fails
+    pub async fn wait(mut self) -> Result<Output> {
+        match self
+            .wait_until(std::future::pending::<Infallible>())
+            .await?
+        {
+            Waited::Exited(output) => self.finish(output).await,
+            Waited::Until(never) => match never {},
+        }
+    }
+
+    /// Wait for the command to exit like [`Running::wait`], or for `until` to complete,
+    /// whichever comes first
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if waiting fails
+    pub async fn wait_until<T>(&mut self, until: impl Future<Output = T>) -> Result<Waited<T>> {
+        forward_signals(&self.handle, &mut self.waiting, until).await
+    }
+
+    /// Clean up after the command exited: drain its redacted output and remove its files
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if writing its output fails
+    pub async fn finish(self, output: Output) -> Result<Output> {
+        drop(self.handle);
+        let drained = tokio::time::sleep(DRAIN_GRACE);
+        tokio::pin!(drained);
+        for pipe in self.pipes {
+            tokio::select! {
+                res = pipe => {
+                    res.map_err(|e| Error::Message(e.to_string()))??;
+                }
+                () = &mut drained => break,
+            }
+        }
+        drop(self.files);
+        Ok(output)
+    }
+
+    /// Ask the command to stop with SIGTERM, killing it if it is still running after a grace
+    /// period
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command cannot be stopped
+    pub async fn stop(mut self) -> Result<Output> {
+        #[cfg(unix)]
+        self.signal(libc::SIGTERM);
+        #[cfg(windows)]
+        self.handle.kill()?;
+        let output = match self.wait_until(tokio::time::sleep(STOP_GRACE)).await? {
+            Waited::Exited(output) => output,
+            Waited::Until(()) => {
+                self.handle.kill()?;
+                (&mut self.waiting)
+                    .await
+                    .map_err(|e| Error::Message(e.to_string()))??
+            }
+        };
+        self.finish(output).await
+    }
+
+    /// Send a signal to the command
+    #[cfg(unix)]
+    pub fn signal(&self, signal: i32) {
+        for pid in self.handle.pids() {
+            // the child may have exited in the meantime, nothing to signal then
+            #[allow(clippy::cast_possible_wrap)]
+            let _ = unsafe { libc::kill(pid as libc::pid_t, signal) };
+        }
+    }
+
+    /// Hand new values to the running command: its files are rewritten, and the new values
+    /// are redacted from its output along with the ones it started with
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the files cannot be written
+    pub fn update(&self, kvs: &[KV], files: &[KV]) -> Result<()> {
+        if let Some(secret_files) = &self.files {
+            secret_files.put(files)?;
+        }
+        self.secrets
+            .write()
+            .map_err(|e| Error::Message(e.to_string()))?
+            .extend(kvs.iter().chain(files).cloned());
+        Ok(())
+    }
+}
+
+/// Copy `reader` to `writer` line by line, redacting each line. The rest of a line is
+/// written once nothing more arrives for [`PARTIAL_LINE_IDLE`], except for an end that may
+/// be the start of a secret or of a character, see [`held_back`]. Reading and writing run
+/// on threads of their own, a process holding the pipe open must not hold up the runtime.
+fn redact_pipe<W: Write + 'static>(
+    mut reader: os_pipe::PipeReader,
+    writer: fn() -> W,
+    redactor: &Redactor,
+    secrets: Arc<RwLock<Vec<KV>>>,
+) -> tokio::sync::oneshot::Receiver<io::Result<()>> {
+    let redactor = redactor.clone();
+    let (chunks, received) = std::sync::mpsc::channel::<io::Result<Vec<u8>>>();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            let chunk = match reader.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => Ok(buf[..n].to_vec()),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+            if chunks.send(chunk).is_err() {
+                return;
+            }
+        }
+    });
+    let held = secrets.clone();
+    let write = move |text: &[u8]| -> io::Result<()> {
+        let kvs = secrets
+            .read()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let mut writer = writer();
+        writer.write_all(
+            redactor
+                .redact_string(&String::from_utf8_lossy(text), &kvs)
+                .as_bytes(),
+        )?;
+        writer.flush()
+    };
+    let (done, drained) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let mut pending = Vec::new();
+        // whether the rest of a line waits to be shown after a pause
+        let mut partial = false;
+        let res = loop {
+            let chunk = if partial {
+                received.recv_timeout(PARTIAL_LINE_IDLE)
+            } else {
+                received
+                    .recv()
+                    .map_err(|_| std::sync::mpsc::RecvTimeoutError::Disconnected)
+            };
+            let res = match chunk {
+                Ok(Ok(chunk)) => {
+                    pending.extend(chunk);
+                    let lines = pending
+                        .iter()
+                        .rposition(|b| *b == b'\n')
+                        .map_or(0, |end| end + 1);
+                    let rest = pending.split_off(lines);
+                    // secrets are matched within lines, write them one at a time
+                    let res = pending
+                        .split_inclusive(|b| *b == b'\n')
+                        .try_for_each(&write);
+                    pending = rest;
+                    partial = !pending.is_empty();
+                    res
+                }
+                Ok(Err(err)) => break Err(err),
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    partial = false;
+                    let keep = held
+                        .read()
+                        .map(|kvs| held_back(&pending, &kvs))
+                        .map_err(|e| io::Error::other(e.to_string()));
+                    keep.and_then(|keep| {
+                        let rest = pending.split_off(pending.len() - keep);
+                        let res = write(&pending);
+                        pending = rest;
+                        res
+                    })
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                    break write(&pending);
+                }
+            };
+            if res.is_err() {
+                break res;
+            }
+        };
+        let _ = done.send(res);
+    });
+    drained
+}
+
+/// How many bytes at the end of a partial line to keep until more arrives: those that may
+/// be the start of a secret, which would otherwise be written unredacted in pieces, and
+/// those of a character cut short
+fn held_back(pending: &[u8], secrets: &[KV]) -> usize {
+    let cut_char = match std::str::from_utf8(pending) {
+        Err(err) if err.error_len().is_none() => pending.len() - err.valid_up_to(),
+        _ => 0,
+    };
+    let secret_start = (1..=pending.len())
+        .rev()
+        .find(|&len| {
+            let end = &pending[pending.len() - len..];
+            secrets.iter().any(|kv| {
+                let value = kv.value.as_bytes();
+                value.len() > len && value.starts_with(end)
+            })
+        })
+        .unwrap_or(0);
+    cut_char.max(secret_start)
+}
+
+#[cfg(unix)]
+async fn forward_signals<T>(
+    handle: &duct::Handle,
+    waiting: &mut tokio::task::JoinHandle<io::Result<Output>>,
+    until: impl Future<Output = T>,
+) -> Result<Waited<T>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    // the child shares our process group: when that is the terminal's foreground group,
+    // Ctrl-C already reached it, and like a shell we only keep SIGINT from killing us
+    let foreground = unsafe {
+        libc::isatty(libc::STDIN_FILENO) == 1
+            && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+    };
+    tokio::pin!(until);
+    loop {
+        let forwarded = tokio::select! {
+            res = &mut *waiting => {
+                return Ok(Waited::Exited(res.map_err(|e| Error::Message(e.to_string()))??));
+            }
+            value = &mut until => return Ok(Waited::Until(value)),
+            _ = interrupt.recv() => {
+                if foreground {
+                    continue;
+                }
+                libc::SIGINT
+            }
+            _ = terminate.recv() => libc::SIGTERM,
+            _ = hangup.recv() => libc::SIGHUP,
+        };
+        for pid in handle.pids() {
+            // the child may have exited in the meantime, nothing to forward then
+            #[allow(clippy::cast_possible_wrap)]
+            let _ = unsafe { libc::kill(pid as libc::pid_t, forwarded) };
+        }
+    }
+}
+
+#[cfg(windows)]
+async fn forward_signals<T>(
+    _handle: &duct::Handle,
+    waiting: &mut tokio::task::JoinHandle<io::Result<Output>>,
+    until: impl Future<Output = T>,
+) -> Result<Waited<T>> {
+    tokio::select! {
+        res = waiting => Ok(Waited::Exited(res.map_err(|e| Error::Message(e.to_string()))??)),
+        value = until => Ok(Waited::Until(value)),
+    }
+}
+
+/// The exit code a shell reports for a finished command: its own, or 128 + n when it was
+/// killed by signal n
+#[must_use]
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+fn expression(
+    cmdstr: &str,
+    env_kvs: &[(String, String)],
+    opts: &Opts<'_>,
+) -> Result<duct::Expression> {
     let words = if opts.sh {
         shell_command_argv(cmdstr.into())
     } else {
@@ -47,15 +700,13 @@ pub fn cmd(cmdstr: &str, env_kvs: &[(String, String)], opts: &Opts<'_>) -> Resul
     )
 }
 
-fn cmd_slice(words: &[&str], env_kvs: &[(String, String)], opts: &Opts<'_>) -> Result<Output> {
+fn cmd_slice(
+    words: &[&str],
+    env_kvs: &[(String, String)],
+    opts: &Opts<'_>,
+) -> Result<duct::Expression> {
     // env handling
-    let mut env_map: HashMap<_, _> = if opts.reset_env {
-        std::env::vars()
-            .filter(|(k, _)| ENV_OK.contains(&k.as_str()))
-            .collect()
-    } else {
-        std::env::vars().collect()
-    };
+    let mut env_map = opts.env.inherit(std::env::vars(), opts.reset_env);
 
     for (k, v) in env_kvs {
         env_map.insert(k.clone(), v.clone());
@@ -66,15 +717,14 @@ fn cmd_slice(words: &[&str], env_kvs: &[(String, String)], opts: &Opts<'_>) -> R
         .split_first()
         .ok_or_else(|| Error::Message("command has not enough arguments".to_string()))?;
 
-    let mut expr = duct::cmd(Path::new(first), rest)
-        .dir(opts.pwd)
-        .full_env(&env_map);
+    // a program name, not a path: it is looked up in PATH rather than in `pwd`
+    let mut expr = duct::cmd(*first, rest).dir(opts.pwd).full_env(&env_map);
 
     if opts.capture {
         expr = expr.stdout_capture();
     }
 
-    Ok(expr.run()?)
+    Ok(expr)
 }
 
 #[cfg(unix)]
@@ -103,7 +753,10 @@ mod tests {
     use teller_providers::providers::ProviderKind;
 
     use super::cmd;
-    use super::Opts;
+    use super::{
+        exit_code, pattern_matches, redact_pipe, run, ExecConfig, OnChange, Opts, SecretFiles,
+    };
+    use crate::redact::Redactor;
 
     #[test]
     #[cfg(not(windows))]
@@ -126,6 +779,10 @@ mod tests {
                 capture: true,
                 reset_env: true,
                 sh: true,
+                redact: false,
+                env: ExecConfig::default(),
+                files_dir: None,
+                watch: None,
             },
         )
         .unwrap();
@@ -133,6 +790,202 @@ mod tests {
         assert_debug_snapshot!(s);
     }
 
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn reports_exit_codes() {
+        let opts = Opts {
+            pwd: Path::new("."),
+            capture: false,
+            reset_env: true,
+            sh: true,
+            redact: false,
+            env: ExecConfig::default(),
+            files_dir: None,
+            watch: None,
+        };
+        let redactor = Redactor::new();
+        let out = run("exit 3", &[], &[], &redactor, &opts).await.unwrap();
+        assert_eq!(exit_code(&out.status), 3);
+        let out = run("kill -TERM $$", &[], &[], &redactor, &opts)
+            .await
+            .unwrap();
+        assert_eq!(exit_code(&out.status), 143);
+    }
+
+    #[test]
+    fn writes_secret_files() {
+        let base = tempfile::tempdir().unwrap();
+        let (files, vars) = SecretFiles::write(
+            Some(base.path()),
+            &[KV::from_kv("DB_PASS", "s3cret"), KV::from_kv("EMPTY", "")],
+        )
+        .unwrap();
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars[0].0, "DB_PASS_FILE");
+        assert_eq!(std::fs::read_to_string(&vars[0].1).unwrap(), "s3cret");
+        assert_eq!(std::fs::read_to_string(&vars[1].1).unwrap(), "");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |p: &str| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&vars[0].1), 0o600);
+            assert_eq!(mode(files.path().to_str().unwrap()), 0o700);
+        }
+
+        let dir = files.path();
+        drop(files);
+        assert!(!dir.exists());
+
+        assert!(SecretFiles::write(Some(base.path()), &[KV::from_kv("../x", "1")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_file_vars_shadowing_keys() {
+        let base = tempfile::tempdir().unwrap();
+        let opts = Opts {
+            pwd: Path::new("."),
+            capture: false,
+            reset_env: true,
+            sh: true,
+            redact: false,
+            env: ExecConfig::default(),
+            files_dir: Some(base.path()),
+            watch: None,
+        };
+        let err = run(
+            "true",
+            &[KV::from_kv("DB_PASS_FILE", "/etc/passwd")],
+            &[KV::from_kv("DB_PASS", "s3cret")],
+            &Redactor::new(),
+            &opts,
+        )
+        .await
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("'DB_PASS'") && message.contains("'DB_PASS_FILE'"));
+        assert_eq!(std::fs::read_dir(base.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn filters_inherited_env() {
+        assert!(pattern_matches("XDG_*", "XDG_CONFIG_HOME"));
+        assert!(pattern_matches("*_TOKEN", "GITHUB_TOKEN"));
+        assert!(pattern_matches("A*B*C", "AxxBxC"));
+        assert!(!pattern_matches("*_TOKEN", "GITHUB_TOKENS"));
+        assert!(!pattern_matches("HOME", "HOMEDIR"));
+
+        let vars = || {
+            [
+                "PATH",
+                "HOME",
+                "KUBECONFIG",
+                "XDG_DATA_HOME",
+                "GITHUB_TOKEN",
+            ]
+            .iter()
+            .map(|k| ((*k).to_string(), String::new()))
+        };
+        let names = |rules: &ExecConfig, reset: bool| {
+            let mut names = rules.inherit(vars(), reset).into_keys().collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let mut rules = ExecConfig::default();
+        assert_eq!(names(&rules, true), vec!["HOME", "PATH"]);
+        rules.extend(&ExecConfig {
+            keep: vec!["KUBECONFIG".to_string()],
+            keep_patterns: vec!["XDG_*".to_string(), "*_TOKEN".to_string()],
+            deny: vec!["*_TOKEN".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            names(&rules, true),
+            vec!["HOME", "KUBECONFIG", "PATH", "XDG_DATA_HOME"]
+        );
+        assert_eq!(
+            names(&rules, false),
+            vec!["HOME", "KUBECONFIG", "PATH", "XDG_DATA_HOME"]
+        );
+        rules.keep_defaults = false;
+        assert_eq!(names(&rules, true), vec!["KUBECONFIG", "XDG_DATA_HOME"]);
+    }
+
+    static REDACTED: std::sync::Mutex<Vec<u8>> = std::sync::Mutex::new(Vec::new());
+
+    struct Redacted;
+
+    impl std::io::Write for Redacted {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            REDACTED.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn redacts_lines_and_shows_prompts() {
+        use std::io::Write;
+
+        let (reader, mut writer) = os_pipe::pipe().unwrap();
+        let secrets = vec![KV::from_kv("PASS", "hunter2")];
+        let drained = redact_pipe(
+            reader,
+            || Redacted,
+            &Redactor::new(),
+            std::sync::Arc::new(std::sync::RwLock::new(secrets)),
+        );
+        let mut paused = |text: &[u8]| {
+            writer.write_all(text).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            String::from_utf8_lossy(&REDACTED.lock().unwrap()).into_owned()
+        };
+        // the prompt shows while the pipe is still open
+        assert_eq!(
+            paused(b"pass hunter2\npassword: "),
+            "pass [REDACTED]\npassword: "
+        );
+        // neither half of a secret shows on its own
+        assert_eq!(paused(b"hun"), "pass [REDACTED]\npassword: ");
+        assert_eq!(
+            paused(b"ter2 caf\xc3"),
+            "pass [REDACTED]\npassword: [REDACTED] caf"
+        );
+        assert_eq!(
+            paused(b"\xa9\n"),
+            "pass [REDACTED]\npassword: [REDACTED] caf\u{e9}\n"
+        );
+        drop(writer);
+        drained.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn parses_change_actions() {
+        assert_eq!("restart".parse::<OnChange>().unwrap(), OnChange::Restart);
+        #[cfg(unix)]
+        {
+            assert_eq!(
+                "signal:HUP".parse::<OnChange>().unwrap(),
+                OnChange::Signal(libc::SIGHUP)
+            );
+            assert_eq!(
+                "signal:sigusr1".parse::<OnChange>().unwrap(),
+                OnChange::Signal(libc::SIGUSR1)
+            );
+            assert_eq!(
+                "signal:15".parse::<OnChange>().unwrap(),
+                OnChange::Signal(15)
+            );
+        }
+        assert_eq!(
+            "signal:NOPE".parse::<OnChange>().unwrap_err().to_string(),
+            "unknown signal 'NOPE'"
+        );
+        assert!("reload".parse::<OnChange>().is_err());
+    }
+
     #[ignore]
     #[test]
     fn env_reset() {
@@ -154,6 +1007,10 @@ mod tests {
                 capture: true,
                 reset_env: false, // <-- notice this!
                 sh: false,
+                redact: false,
+                env: ExecConfig::default(),
+                files_dir: None,
+                watch: None,
             },
         )
         .unwrap();
@@ -180,6 +1037,10 @@ mod tests {
                 capture: true,
                 reset_env: true, // <-- reset env
                 sh: false,
+                redact: false,
+                env: ExecConfig::default(),
+                files_dir: None,
+                watch: None,
             },
         )
         .unwrap();
@@ -1,9 +1,13 @@
+pub mod computed;
 pub mod config;
+pub mod doctor;
 pub mod exec;
 pub mod export;
 mod io;
+pub mod policy;
 pub mod redact;
 pub mod scan;
+pub mod schema;
 pub mod teller;
 pub mod template;
 
@@ -0,0 +1,95 @@
+use serde_derive::{Deserialize, Serialize};
+use teller_providers::config::{Sensitivity, KV};
+
+/// Sensitivity thresholds applied to collected values.
+///
+/// ```yaml
+/// policy:
+///   redact_min: Low
+///   export_max: Medium
+///   preview_max: High
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default)]
+pub struct Policy {
+    /// Values at or above this sensitivity are redacted
+    pub redact_min: Sensitivity,
+    /// Values above this sensitivity are refused by export unless explicitly allowed
+    pub export_max: Sensitivity,
+    /// Values above this sensitivity are never previewed when showing values
+    pub preview_max: Sensitivity,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            redact_min: Sensitivity::None,
+            export_max: Sensitivity::Medium,
+            preview_max: Sensitivity::High,
+        }
+    }
+}
+
+/// The sensitivity a KV was collected with, `None` if it has no metadata
+#[must_use]
+pub fn sensitivity_of(kv: &KV) -> Sensitivity {
+    kv.meta
+        .as_ref()
+        .map(|m| m.sensitivity.clone())
+        .unwrap_or_default()
+}
+
+impl Policy {
+    #[must_use]
+    pub fn exportable(&self, kv: &KV) -> bool {
+        sensitivity_of(kv) <= self.export_max
+    }
+
+    #[must_use]
+    pub fn previewable(&self, kv: &KV) -> bool {
+        sensitivity_of(kv) <= self.preview_max
+    }
+
+    /// Keys that cannot be exported under this policy
+    #[must_use]
+    pub fn unexportable_keys(&self, kvs: &[KV]) -> Vec<String> {
+        kvs.iter()
+            .filter(|kv| !self.exportable(kv))
+            .map(|kv| kv.key.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teller_providers::config::MetaInfo;
+
+    use super::*;
+
+    fn kv_with(key: &str, sensitivity: Sensitivity) -> KV {
+        KV {
+            meta: Some(MetaInfo {
+                sensitivity,
+                ..Default::default()
+            }),
+            ..KV::from_kv(key, "value")
+        }
+    }
+
+    #[test]
+    fn default_policy() {
+        let policy = Policy::default();
+        assert!(policy.exportable(&KV::from_kv("PLAIN", "value")));
+        assert!(policy.exportable(&kv_with("LOG_LEVEL", Sensitivity::Medium)));
+        assert!(!policy.exportable(&kv_with("API_KEY", Sensitivity::High)));
+        assert!(policy.previewable(&kv_with("API_KEY", Sensitivity::High)));
+        assert!(!policy.previewable(&kv_with("ROOT_PASS", Sensitivity::Critical)));
+        assert_eq!(
+            policy.unexportable_keys(&[
+                kv_with("LOG_LEVEL", Sensitivity::Low),
+                kv_with("ROOT_PASS", Sensitivity::Critical),
+            ]),
+            vec!["ROOT_PASS".to_string()]
+        );
+    }
+}
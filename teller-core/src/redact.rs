@@ -4,14 +4,29 @@ use std::{
 };
 
 // use crate::{Result, KV};
-use teller_providers::config::KV;
+use teller_providers::config::{Sensitivity, KV};
 
-pub struct Redactor {}
+use crate::policy::sensitivity_of;
+
+#[derive(Clone)]
+pub struct Redactor {
+    min_sensitivity: Sensitivity,
+}
 
 impl Redactor {
     #[must_use]
     pub const fn new() -> Self {
-        Self {}
+        Self::with_min_sensitivity(Sensitivity::None)
+    }
+
+    /// A redactor that only redacts values at or above the given sensitivity
+    #[must_use]
+    pub const fn with_min_sensitivity(min_sensitivity: Sensitivity) -> Self {
+        Self { min_sensitivity }
+    }
+
+    fn applies(&self, kv: &KV) -> bool {
+        sensitivity_of(kv) >= self.min_sensitivity
     }
 
     /// Redact a reader into writer
@@ -28,16 +43,19 @@ impl Redactor {
         for line in reader.lines().map_while(Result::ok) {
             let redacted = self.redact_string(line.as_str(), kvs);
             writer.write_all(redacted.as_bytes())?;
-            writer.write_all(&[b'\n'])?; // TODO: support crlf for windows
+            writer.write_all(b"\n")?; // TODO: support crlf for windows
             writer.flush()?;
         }
         Ok(())
     }
 
     #[must_use]
-    pub fn redact_string<'a>(&'a self, message: &'a str, kvs: &[KV]) -> Cow<'_, str> {
+    pub fn redact_string<'a>(&'a self, message: &'a str, kvs: &[KV]) -> Cow<'a, str> {
         if self.has_match(message, kvs) {
             let mut redacted = message.to_string();
+            // longest values first, so a value built from others is redacted as a whole
+            let mut kvs = kvs.iter().filter(|kv| self.applies(kv)).collect::<Vec<_>>();
+            kvs.sort_by_key(|kv| std::cmp::Reverse(kv.value.len()));
             for kv in kvs {
                 // only replace values with at least 2 chars
                 if kv.value.len() >= 2 {
@@ -58,7 +76,8 @@ impl Redactor {
 
     #[must_use]
     pub fn has_match<'a>(&'a self, message: &'a str, kvs: &[KV]) -> bool {
-        kvs.iter().any(|kv| message.contains(&kv.value))
+        kvs.iter()
+            .any(|kv| self.applies(kv) && message.contains(&kv.value))
     }
 }
 
@@ -73,7 +92,10 @@ mod tests {
     use std::io::{BufReader, BufWriter};
 
     use stringreader::StringReader;
-    use teller_providers::{config::ProviderInfo, providers::ProviderKind};
+    use teller_providers::{
+        config::{PathMap, ProviderInfo},
+        providers::ProviderKind,
+    };
 
     use super::*;
 
@@ -82,7 +104,7 @@ mod tests {
         let data = "foobar\nfoobaz\n";
         let mut reader = BufReader::new(StringReader::new(data));
         let mut writer = BufWriter::new(Vec::new());
-        let redactor = Redactor {};
+        let redactor = Redactor::new();
 
         redactor.redact(&mut reader, &mut writer, &[]).unwrap();
         let s = String::from_utf8(writer.into_inner().unwrap()).unwrap();
@@ -94,7 +116,7 @@ mod tests {
         let data = "foobar\nfoobaz\n";
         let mut reader = BufReader::new(StringReader::new(data));
         let mut writer = BufWriter::new(Vec::new());
-        let redactor = Redactor {};
+        let redactor = Redactor::new();
 
         redactor
             .redact(
@@ -114,4 +136,25 @@ mod tests {
         let s = String::from_utf8(writer.into_inner().unwrap()).unwrap();
         assert_eq!(s, "foobar\n[REDACTED]\n");
     }
+
+    #[test]
+    fn redact_min_sensitivity() {
+        let provider = ProviderInfo {
+            kind: ProviderKind::Inmem,
+            name: "test".to_string(),
+        };
+        let mut pm = PathMap::from_path("some/path");
+        pm.sensitivity = Sensitivity::Low;
+        let low = KV::from_value("loglevel", "k1", "k1", &pm, provider.clone());
+        pm.sensitivity = Sensitivity::High;
+        let high = KV::from_value("hunter2", "k2", "k2", &pm, provider);
+
+        let redactor = Redactor::with_min_sensitivity(Sensitivity::Medium);
+        let kvs = [low, high];
+        assert_eq!(
+            redactor.redact_string("loglevel hunter2", &kvs),
+            "loglevel [REDACTED]"
+        );
+        assert!(!redactor.has_match("loglevel", &kvs));
+    }
 }
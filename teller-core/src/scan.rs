@@ -146,7 +146,7 @@ mod tests {
             kind: ProviderKind::Inmem,
             name: "test".to_string(),
         };
-        let kvs = vec![
+        let kvs = [
             KV::from_literal("/some/path", "key1", "hashicorp", provider.clone()),
             KV::from_literal("/some/path", "key1", "dont-find-me", provider.clone()),
             KV::from_literal("/some/path", "key1", "trooper123", provider.clone()),
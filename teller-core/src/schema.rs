@@ -0,0 +1,192 @@
+//! The contract of the collected environment
+//!
+//! ## Example configuration
+//!
+//! ```yaml
+//! schema:
+//!   DATABASE_URL: { url: true }
+//!   PORT: { integer: true }
+//!   LOG_LEVEL: { one_of: [debug, info, warn] }
+//!   API_TOKEN: { min_length: 32, regex: '^tk_' }
+//!   SENTRY_DSN: { required: false, url: true }
+//! ```
+//!
+//! Every key listed is required unless it sets `required: false`, and non-empty when it
+//! sets `non_empty: true`. Other rules only apply to keys that are present. Violations
+//! never include the offending value.
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use teller_providers::config::KV;
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+const fn default_required() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_true(b: &bool) -> bool {
+    *b
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyRule {
+    #[serde(default = "default_required", skip_serializing_if = "is_true")]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub non_empty: bool,
+    #[serde(default, with = "regex_opt", skip_serializing_if = "Option::is_none")]
+    pub regex: Option<Regex>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub url: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub integer: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub one_of: Vec<String>,
+}
+
+mod regex_opt {
+    use regex::Regex;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: Serializer>(re: &Option<Regex>, serializer: S) -> Result<S::Ok, S::Error> {
+        match re {
+            Some(re) => serializer.serialize_str(re.as_str()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Regex>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|re| Regex::new(&re).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+impl KeyRule {
+    /// Why `value` breaks this rule, if it does
+    fn check(&self, value: &str) -> Option<String> {
+        if self.non_empty && value.is_empty() {
+            return Some("is empty".to_string());
+        }
+        if let Some(min_length) = self.min_length {
+            if value.chars().count() < min_length {
+                return Some(format!("is shorter than {min_length} characters"));
+            }
+        }
+        if let Some(re) = &self.regex {
+            if !re.is_match(value) {
+                return Some(format!("does not match '{}'", re.as_str()));
+            }
+        }
+        if self.url && url::Url::parse(value).is_err() {
+            return Some("is not a URL".to_string());
+        }
+        if self.integer && value.parse::<i64>().is_err() {
+            return Some("is not an integer".to_string());
+        }
+        if !self.one_of.is_empty() && !self.one_of.iter().any(|v| v == value) {
+            return Some(format!("is not one of: {}", self.one_of.join(", ")));
+        }
+        None
+    }
+}
+
+/// A key breaking its schema rule
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub key: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.key, self.message)
+    }
+}
+
+/// Check collected `kvs` against `schema`, in schema order. When `partial`, missing keys
+/// are not reported, since they may come from maps that were not read.
+#[must_use]
+pub fn validate(schema: &BTreeMap<String, KeyRule>, kvs: &[KV], partial: bool) -> Vec<Violation> {
+    schema
+        .iter()
+        .filter_map(|(key, rule)| {
+            let message = match kvs.iter().find(|kv| &kv.key == key) {
+                Some(kv) => rule.check(&kv.value)?,
+                None if rule.required && !partial => "is missing".to_string(),
+                None => return None,
+            };
+            Some(Violation {
+                key: key.clone(),
+                message,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r"
+DATABASE_URL: { url: true }
+PORT: { integer: true }
+LOG_LEVEL: { one_of: [debug, info] }
+API_TOKEN: { min_length: 8, regex: '^tk_' }
+EMPTY: { non_empty: true }
+SENTRY_DSN: { required: false, url: true }
+MISSING: {}
+";
+
+    #[test]
+    fn reports_every_violation() {
+        let schema: BTreeMap<String, KeyRule> = serde_yaml::from_str(SCHEMA).unwrap();
+        let kvs = [
+            KV::from_kv("DATABASE_URL", "postgres://db/app"),
+            KV::from_kv("PORT", "80a"),
+            KV::from_kv("LOG_LEVEL", "trace"),
+            KV::from_kv("API_TOKEN", "tk_1"),
+            KV::from_kv("EMPTY", ""),
+        ];
+        let violations = validate(&schema, &kvs, false)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            violations,
+            vec![
+                "API_TOKEN is shorter than 8 characters",
+                "EMPTY is empty",
+                "LOG_LEVEL is not one of: debug, info",
+                "MISSING is missing",
+                "PORT is not an integer",
+            ]
+        );
+        assert_eq!(validate(&schema, &kvs, true).len(), 4);
+    }
+
+    #[test]
+    fn passes_valid_values() {
+        let schema: BTreeMap<String, KeyRule> = serde_yaml::from_str(SCHEMA).unwrap();
+        let kvs = [
+            KV::from_kv("DATABASE_URL", "postgres://db/app"),
+            KV::from_kv("PORT", "8080"),
+            KV::from_kv("LOG_LEVEL", "info"),
+            KV::from_kv("API_TOKEN", "tk_12345678"),
+            KV::from_kv("EMPTY", "x"),
+            KV::from_kv("MISSING", ""),
+        ];
+        assert!(validate(&schema, &kvs, false).is_empty());
+    }
+}
@@ -1,23 +1,119 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{BufRead, Write};
 use std::path::Path;
 use std::process::Output;
+use std::time::Duration;
 
-use teller_providers::config::PathMap;
+use futures::stream::{self, StreamExt};
+use teller_providers::config::{Delivery, ListEntry, PathMap, VersionInfo};
 use teller_providers::Provider;
 // use csv::WriterBuilder;
-use teller_providers::{config::KV, registry::Registry, Result as ProviderResult};
+use teller_providers::{
+    config::KV,
+    registry::{Registry, RegistryBuilder},
+    Result as ProviderResult,
+};
 
 use crate::redact::Redactor;
 use crate::template;
 use crate::{
+    computed,
     config::{Config, Match},
-    exec, export, scan, Error, Result,
+    exec, export, scan,
+    schema::{self, Violation},
+    Error, Result,
 };
 
+/// Number of maps fetched concurrently when the configuration does not set `concurrency`
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// An optional map that was left out of a collection
+#[derive(Debug, Clone)]
+pub struct SkippedMap {
+    pub provider: String,
+    pub map_id: String,
+    pub path: String,
+    pub error: String,
+}
+
+/// A key left out of a collection, because another map provides the same key with a
+/// higher priority, or with the same priority later in the configuration
+#[derive(Debug, Clone)]
+pub struct Shadowed {
+    pub kv: KV,
+    pub by: KV,
+}
+
+/// The result of collecting all maps, along with the optional maps that were skipped
+/// and the keys that were shadowed
+#[derive(Debug, Clone, Default)]
+pub struct Collected {
+    /// One value per key
+    pub kvs: Vec<KV>,
+    /// Every collected value, shadowed ones included, in configuration order
+    pub all: Vec<KV>,
+    pub skipped: Vec<SkippedMap>,
+    pub shadowed: Vec<Shadowed>,
+}
+
+/// Narrows the maps read while collecting. Each non-empty selector must match a map for it
+/// to be read (a map matches a selector by any of its values), and excluded maps are
+/// never read. Maps are given by id, or as `<provider>/<map id>`.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    pub providers: Vec<String>,
+    pub map_ids: Vec<String>,
+    pub tags: Vec<String>,
+    pub exclude_maps: Vec<String>,
+}
+
+impl Selection {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+            && self.map_ids.is_empty()
+            && self.tags.is_empty()
+            && self.exclude_maps.is_empty()
+    }
+
+    /// Whether the map `pm` of `provider` is selected
+    #[must_use]
+    pub fn includes(&self, provider: &str, pm: &PathMap) -> bool {
+        let is_map = |selector: &String| match selector.split_once('/') {
+            Some((p, id)) => p == provider && id == pm.id,
+            None => *selector == pm.id,
+        };
+        (self.providers.is_empty() || self.providers.iter().any(|p| p == provider))
+            && (self.map_ids.is_empty() || self.map_ids.iter().any(is_map))
+            && (self.tags.is_empty() || self.tags.iter().any(|t| pm.tags.contains(t)))
+            && !self.exclude_maps.iter().any(is_map)
+    }
+}
+
+/// A directional copy between two maps, derived from `source`/`sink` declarations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncPair {
+    pub from_provider: String,
+    pub from_map_id: String,
+    pub to_provider: String,
+    pub to_map_id: String,
+}
+
+impl std::fmt::Display for SyncPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} -> {}/{}",
+            self.from_provider, self.from_map_id, self.to_provider, self.to_map_id
+        )
+    }
+}
+
 pub struct Teller {
     registry: Registry,
     config: Config,
+    selection: Selection,
+    strict: bool,
 }
 
 impl Teller {
@@ -27,13 +123,83 @@ impl Teller {
     ///
     /// This function will return an error if loading fails
     pub async fn from_config(config: &Config) -> teller_providers::Result<Self> {
-        let registry = Registry::new(&config.providers).await?;
+        Self::from_config_with_registry(config, RegistryBuilder::new()).await
+    }
+
+    /// Build from config, with custom providers: instances registered by name and
+    /// factories for kinds teller does not know
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if loading fails
+    pub async fn from_config_with_registry(
+        config: &Config,
+        registry: RegistryBuilder,
+    ) -> teller_providers::Result<Self> {
+        // a pinned version must never be silently served as latest
+        for (name, providercfg) in &config.providers {
+            if let Some(pm) = providercfg.maps.iter().find(|pm| pm.version.is_some()) {
+                if !providercfg.kind.is_versioned() {
+                    return Err(teller_providers::Error::Message(format!(
+                        "map '{}' pins a version, but provider '{name}' ({}) does not keep \
+                         versions",
+                        pm.id, providercfg.kind
+                    )));
+                }
+            }
+        }
+        let registry = registry.build(&config.providers)?;
         Ok(Self {
             registry,
             config: config.clone(),
+            selection: Selection::default(),
+            strict: false,
         })
     }
 
+    /// Only read the maps in `selection` while collecting
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the selection names an unknown provider,
+    /// or matches no map at all
+    pub fn with_selection(mut self, selection: Selection) -> Result<Self> {
+        if let Some(unknown) = selection
+            .providers
+            .iter()
+            .find(|p| !self.config.providers.contains_key(*p))
+        {
+            return Err(Error::Message(format!("cannot find provider '{unknown}'")));
+        }
+        let matched = self.config.providers.iter().any(|(name, providercfg)| {
+            providercfg
+                .maps
+                .iter()
+                .any(|pm| selection.includes(name, pm))
+        });
+        if !matched && !selection.is_empty() {
+            return Err(Error::Message(
+                "the provider and map selection matches no map".to_string(),
+            ));
+        }
+        self.selection = selection;
+        Ok(self)
+    }
+
+    /// Fail collecting when maps of the same priority provide the same key, instead of
+    /// letting the later one win
+    #[must_use]
+    pub const fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The configuration this instance was built from
+    #[must_use]
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
     /// Build from YAML
     ///
     /// # Errors
@@ -43,22 +209,165 @@ impl Teller {
         let config = Config::from_path(file)?;
         Self::from_config(&config).await.map_err(Error::Provider)
     }
-    /// Collects kvs from all provider maps in the current configuration
+    /// Collects kvs from all selected provider maps in the current configuration.
+    /// Maps are fetched concurrently (up to the configured `concurrency`), results
+    /// keep the configuration order.
     ///
     /// # Errors
     ///
     /// This function will return an error if IO fails
     pub async fn collect(&self) -> ProviderResult<Vec<KV>> {
-        let mut res = Vec::new();
+        Ok(self.collect_with_report().await?.kvs)
+    }
+
+    /// Collects kvs like [`Teller::collect`], and reports which `optional` maps
+    /// were skipped because of a tolerated error.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if IO fails
+    pub async fn collect_with_report(&self) -> ProviderResult<Collected> {
+        let mut fetches = Vec::new();
         for (name, providercfg) in &self.config.providers {
-            if let Some(provider) = self.registry.get(name) {
-                for pm in &providercfg.maps {
-                    let kvs = provider.get(pm).await?;
-                    res.push(kvs);
+            for pm in providercfg
+                .maps
+                .iter()
+                .filter(|pm| self.selection.includes(name, pm))
+            {
+                fetches.push(async move {
+                    // a provider that fails to load fails each of its maps
+                    let res = match self.registry.get(name).await {
+                        Ok(Some(provider)) => provider.get(pm).await,
+                        Ok(None) => Ok(vec![]),
+                        Err(err) => Err(err),
+                    };
+                    (name, pm, res)
+                });
+            }
+        }
+        let results = stream::iter(fetches)
+            .buffered(self.concurrency())
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut collected = Collected::default();
+        for (name, pm, res) in results {
+            match res {
+                Ok(kvs) => collected.kvs.extend(kvs),
+                Err(err) if pm.optional && self.config.optional_policy.skips(&err) => {
+                    tracing::warn!(
+                        provider = name.as_str(),
+                        map_id = pm.id.as_str(),
+                        error = %err,
+                        "skipping optional map"
+                    );
+                    collected.skipped.push(SkippedMap {
+                        provider: name.clone(),
+                        map_id: pm.id.clone(),
+                        path: pm.path.clone(),
+                        error: err.to_string(),
+                    });
                 }
+                Err(err) => return Err(err),
             }
         }
-        Ok(res.into_iter().flatten().collect::<Vec<_>>())
+        collected.all.clone_from(&collected.kvs);
+        let kvs = std::mem::take(&mut collected.kvs);
+        collected.kvs = self.resolve_conflicts(kvs, &mut collected.shadowed)?;
+
+        // with maps left out, references into them are expected to be missing
+        let strict = self.selection.is_empty() && collected.skipped.is_empty();
+        let computed = computed::resolve(&self.config.computed, &collected.kvs, strict)
+            .map_err(|err| teller_providers::Error::Message(err.to_string()))?;
+        if !computed.is_empty() {
+            collected.all.extend(computed.iter().cloned());
+            let mut kvs = std::mem::take(&mut collected.kvs);
+            kvs.extend(computed);
+            collected.kvs = self.resolve_conflicts(kvs, &mut collected.shadowed)?;
+        }
+        Ok(collected)
+    }
+
+    /// The priority of a collected key: its map's, or else its provider's
+    fn priority_of(&self, kv: &KV) -> i32 {
+        let (Some(provider), Some(path)) = (&kv.provider, &kv.path) else {
+            return 0;
+        };
+        self.config
+            .providers
+            .get(&provider.name)
+            .map_or(0, |providercfg| {
+                providercfg
+                    .maps
+                    .iter()
+                    .find(|pm| pm.id == path.id)
+                    .and_then(|pm| pm.priority)
+                    .unwrap_or(providercfg.priority)
+            })
+    }
+
+    /// How the map a key was read from delivers it to commands
+    fn delivery_of(&self, kv: &KV) -> Delivery {
+        let (Some(provider), Some(path)) = (&kv.provider, &kv.path) else {
+            return Delivery::default();
+        };
+        self.config
+            .providers
+            .get(&provider.name)
+            .and_then(|providercfg| providercfg.maps.iter().find(|pm| pm.id == path.id))
+            .map(|pm| pm.deliver)
+            .unwrap_or_default()
+    }
+
+    /// Keep one value per key: the one with the highest priority, or the last one among
+    /// equals in collection order, which is by provider name and then by map order. Others
+    /// are moved to `shadowed`, or fail in strict mode when priorities tie.
+    fn resolve_conflicts(
+        &self,
+        kvs: Vec<KV>,
+        shadowed: &mut Vec<Shadowed>,
+    ) -> ProviderResult<Vec<KV>> {
+        let mut winners: HashMap<String, usize> = HashMap::new();
+        for (i, kv) in kvs.iter().enumerate() {
+            let Some(&winner) = winners.get(&kv.key) else {
+                winners.insert(kv.key.clone(), i);
+                continue;
+            };
+            let (current, challenger) = (self.priority_of(&kvs[winner]), self.priority_of(kv));
+            if current == challenger && self.strict {
+                return Err(teller_providers::Error::Message(format!(
+                    "duplicate key '{}' in {} and {}, give one of them a higher priority",
+                    kv.key,
+                    origin(&kvs[winner]),
+                    origin(kv)
+                )));
+            }
+            if challenger >= current {
+                winners.insert(kv.key.clone(), i);
+            }
+        }
+
+        for (i, kv) in kvs.iter().enumerate() {
+            if winners[&kv.key] != i {
+                shadowed.push(Shadowed {
+                    kv: kv.clone(),
+                    by: kvs[winners[&kv.key]].clone(),
+                });
+            }
+        }
+        Ok(kvs
+            .into_iter()
+            .enumerate()
+            .filter(|(i, kv)| winners[&kv.key] == *i)
+            .map(|(_, kv)| kv)
+            .collect())
+    }
+
+    fn concurrency(&self) -> usize {
+        self.config
+            .concurrency
+            .unwrap_or(DEFAULT_CONCURRENCY)
+            .max(1)
     }
     /// Put a list of KVs into a list of providers, on a specified path
     ///
@@ -68,7 +377,7 @@ impl Teller {
     pub async fn put(&self, kvs: &[KV], map_id: &str, providers: &[String]) -> Result<()> {
         // a target provider has to have the specified path id
         for provider_name in providers {
-            let (provider, pm) = self.get_pathmap_on_provider(map_id, provider_name)?;
+            let (provider, pm) = self.get_pathmap_on_provider(map_id, provider_name).await?;
             provider.put(pm, kvs).await?;
         }
         Ok(())
@@ -82,7 +391,7 @@ impl Teller {
     pub async fn delete(&self, keys: &[String], map_id: &str, providers: &[String]) -> Result<()> {
         // a target provider has to have the specified path id
         for provider_name in providers {
-            let (provider, pm) = self.get_pathmap_on_provider(map_id, provider_name)?;
+            let (provider, pm) = self.get_pathmap_on_provider(map_id, provider_name).await?;
             // 1. if keys is empty, use the default pathmap
             // 2. otherwise, create a new pathmap, with a subset of keys
             if keys.is_empty() {
@@ -99,13 +408,14 @@ impl Teller {
         }
         Ok(())
     }
-    /// Get a provider and pathmap from configuration and registry
+    /// Get a provider and pathmap from configuration and registry, loading the provider
+    /// if it was not used yet
     ///
     /// # Errors
     ///
     /// This function will return an error if operation fails
     #[allow(clippy::borrowed_box)]
-    pub fn get_pathmap_on_provider(
+    pub async fn get_pathmap_on_provider(
         &self,
         map_id: &str,
         provider_name: &String,
@@ -120,27 +430,163 @@ impl Teller {
                 "cannot find path id '{map_id}' in provider '{provider_name}'"
             ))
         })?;
-        let provider = self.registry.get(provider_name).ok_or_else(|| {
+        let provider = self.registry.get(provider_name).await?.ok_or_else(|| {
             Error::Message(format!("cannot get initialized provider '{provider_name}'"))
         })?;
         Ok((provider, pm))
     }
-    /// Run an external command with provider based environment variables
+    /// Run an external command with provider based environment variables. The command
+    /// failing is not an error, its exit status is in the output.
+    ///
+    /// With [`exec::Opts::watch`], values are collected again while the command runs, and
+    /// when they change it is restarted or signaled. Collecting failing then, or giving values
+    /// that break the schema, only warns and keeps the command running as it is. A signaled
+    /// command only sees new values through its files, so signaling needs keys delivered as
+    /// files, and changes it cannot see are warned about.
     ///
     /// # Errors
     ///
-    /// This function will return an error if command fails
+    /// This function will return an error if collecting fails, the command cannot start,
+    /// or it is to be signaled without any key delivered as a file
     pub async fn run<'a>(&self, cmd: &[&str], opts: &exec::Opts<'a>) -> Result<Output> {
         let cmd = shell_words::join(cmd);
-        let kvs = self.collect().await?;
-        let res = exec::cmd(
-            cmd.as_str(),
-            &kvs.iter()
-                .map(|kv| (kv.key.clone(), kv.value.clone()))
-                .collect::<Vec<_>>()[..],
-            opts,
-        )?;
-        Ok(res)
+        let collected = self.collect_with_report().await?;
+        let (mut kvs, mut shadowed) = (collected.kvs, shadowed_values(collected.shadowed));
+        self.enforce_schema(&kvs)?;
+        let redactor = Redactor::with_min_sensitivity(self.config.policy.redact_min.clone());
+        // rules given for this run add to the configured ones
+        let mut env = self.config.exec.clone();
+        env.extend(&opts.env);
+        let opts = exec::Opts {
+            pwd: opts.pwd,
+            capture: opts.capture,
+            sh: opts.sh,
+            reset_env: opts.reset_env,
+            redact: opts.redact,
+            env,
+            files_dir: opts.files_dir,
+            watch: opts.watch,
+        };
+        let (env_kvs, files) = self.delivered(&kvs, &opts);
+        if matches!(
+            opts.watch,
+            Some(exec::Watch {
+                on_change: exec::OnChange::Signal(_),
+                ..
+            })
+        ) && files.is_empty()
+        {
+            return Err(Error::Message(
+                "a signaled command cannot see new values in its environment, deliver keys as \
+                 files with --files or `deliver: file`, or restart it on change"
+                    .to_string(),
+            ));
+        }
+        let mut running = exec::start(cmd.as_str(), &env_kvs, &files, &shadowed, &redactor, &opts)?;
+        let Some(watch) = opts.watch else {
+            return running.wait().await;
+        };
+        // what the running command was started with
+        let (mut started_env, mut started_files) = (env_kvs, files);
+        loop {
+            let changed = match running
+                .wait_until(self.next_change(&kvs, watch.interval))
+                .await?
+            {
+                exec::Waited::Exited(output) => return running.finish(output).await,
+                exec::Waited::Until(changed) => changed,
+            };
+            (kvs, shadowed) = changed;
+            let (env_kvs, files) = self.delivered(&kvs, &opts);
+            match watch.on_change {
+                exec::OnChange::Restart => {
+                    tracing::info!("values changed, restarting the command");
+                    running.stop().await?;
+                    running =
+                        exec::start(cmd.as_str(), &env_kvs, &files, &shadowed, &redactor, &opts)?;
+                    (started_env, started_files) = (env_kvs, files);
+                }
+                exec::OnChange::Signal(signal) => {
+                    let unseen = unseen_changes(&started_env, &started_files, &env_kvs, &files);
+                    if !unseen.is_empty() {
+                        tracing::warn!(
+                            keys = unseen.join(", "),
+                            "the command only sees these changes once restarted"
+                        );
+                    }
+                    tracing::info!(signal, "values changed, signaling the command");
+                    running.update(&[env_kvs, shadowed.clone()].concat(), &files)?;
+                    #[cfg(unix)]
+                    running.signal(signal);
+                    #[cfg(windows)]
+                    let _ = signal;
+                }
+            }
+        }
+    }
+
+    /// Split keys into those a command gets as environment variables, and those it gets as
+    /// files
+    fn delivered(&self, kvs: &[KV], opts: &exec::Opts<'_>) -> (Vec<KV>, Vec<KV>) {
+        kvs.iter()
+            .cloned()
+            .partition(|kv| opts.files_dir.is_none() && self.delivery_of(kv) == Delivery::Env)
+    }
+
+    /// Wait until a selected map may have changed, for at most `timeout`, see
+    /// [`Provider::watch`]
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a provider fails to watch
+    pub async fn wait_for_change(&self, timeout: Duration) -> ProviderResult<()> {
+        let mut watches = Vec::new();
+        for (name, providercfg) in &self.config.providers {
+            for pm in providercfg
+                .maps
+                .iter()
+                .filter(|pm| self.selection.includes(name, pm))
+            {
+                watches.push(Box::pin(async move {
+                    match self.registry.get(name).await? {
+                        Some(provider) => provider.watch(pm, timeout).await.map(|_| ()),
+                        None => {
+                            tokio::time::sleep(timeout).await;
+                            Ok(())
+                        }
+                    }
+                }));
+            }
+        }
+        if watches.is_empty() {
+            tokio::time::sleep(timeout).await;
+            return Ok(());
+        }
+        futures::future::select_all(watches).await.0
+    }
+
+    /// Collect until values differ from `current`, waiting for changes in between. Gives the
+    /// new values, and the shadowed ones.
+    async fn next_change(&self, current: &[KV], interval: Duration) -> (Vec<KV>, Vec<KV>) {
+        loop {
+            if let Err(err) = self.wait_for_change(interval).await {
+                tracing::warn!(error = %err, "watching failed, polling instead");
+                tokio::time::sleep(interval).await;
+            }
+            let collected = match self.collect_with_report().await {
+                Ok(collected) => self.enforce_schema(&collected.kvs).map(|()| collected),
+                Err(err) => Err(err.into()),
+            };
+            match collected {
+                Ok(collected) if !same_values(&collected.kvs, current) => {
+                    return (collected.kvs, shadowed_values(collected.shadowed));
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!(error = %err, "keeping the current values, collecting failed");
+                }
+            }
+        }
     }
 
     /// Redact streams
@@ -150,8 +596,11 @@ impl Teller {
     /// This function will return an error if Is or collecting keys fails
     #[allow(clippy::future_not_send)]
     pub async fn redact<R: BufRead, W: Write>(&self, reader: R, writer: W) -> Result<()> {
-        let kvs = self.collect().await?;
-        let redactor = Redactor::new();
+        let collected = self.collect_with_report().await?;
+        // shadowed values are still secrets
+        let mut kvs = collected.kvs;
+        kvs.extend(shadowed_values(collected.shadowed));
+        let redactor = Redactor::with_min_sensitivity(self.config.policy.redact_min.clone());
         redactor.redact(reader, writer, kvs.as_slice())?;
         Ok(())
     }
@@ -167,16 +616,58 @@ impl Teller {
         Ok(out)
     }
 
-    /// Export KV data
+    /// Export KV data. Values above the policy's `export_max` sensitivity are
+    /// refused unless `allow_sensitive` is set.
     ///
     /// # Errors
     ///
     /// This function will return an error if export fails
-    pub async fn export<'a>(&self, format: &export::Format) -> Result<String> {
+    pub async fn export(&self, format: &export::Format, allow_sensitive: bool) -> Result<String> {
         let kvs = self.collect().await?;
+        self.enforce_schema(&kvs)?;
+        if !allow_sensitive {
+            let refused = self.config.policy.unexportable_keys(&kvs);
+            if !refused.is_empty() {
+                return Err(Error::Message(format!(
+                    "refusing to export sensitive key(s): {}. allow sensitive values explicitly \
+                     to export them",
+                    refused.join(", ")
+                )));
+            }
+        }
         format.export(&kvs)
     }
 
+    /// Check collected keys against the configured `schema`, reporting every violation.
+    /// Keys are not reported missing when only some maps are selected.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if collecting fails
+    pub async fn check(&self) -> Result<Vec<Violation>> {
+        let kvs = self.collect().await?;
+        Ok(schema::validate(
+            &self.config.schema,
+            &kvs,
+            !self.selection.is_empty(),
+        ))
+    }
+
+    fn enforce_schema(&self, kvs: &[KV]) -> Result<()> {
+        let violations = schema::validate(&self.config.schema, kvs, !self.selection.is_empty());
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Message(format!(
+            "collected keys do not match the schema: {}",
+            violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+
     /// Scan a folder recursively for secrets or values
     ///
     /// # Errors
@@ -201,12 +692,14 @@ impl Teller {
         replace: bool,
     ) -> Result<()> {
         // XXX fix &str, &String params
-        let (from_provider, from_pm) =
-            self.get_pathmap_on_provider(from_map_id, &from_provider.to_string())?;
+        let (from_provider, from_pm) = self
+            .get_pathmap_on_provider(from_map_id, &from_provider.to_string())
+            .await?;
         let data = from_provider.get(from_pm).await?;
 
-        let (to_provider, to_pm) =
-            self.get_pathmap_on_provider(to_map_id, &to_provider.to_string())?;
+        let (to_provider, to_pm) = self
+            .get_pathmap_on_provider(to_map_id, &to_provider.to_string())
+            .await?;
 
         if replace {
             to_provider.del(to_pm).await?;
@@ -214,4 +707,659 @@ impl Teller {
         to_provider.put(to_pm, &data).await?;
         Ok(())
     }
+
+    /// Get the kvs of a single map, optionally at a specific version instead of the
+    /// one pinned in configuration
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the map is unknown or the read fails
+    pub async fn get(
+        &self,
+        provider_name: &str,
+        map_id: &str,
+        version: Option<&str>,
+    ) -> Result<Vec<KV>> {
+        let (provider, pm) = self
+            .get_pathmap_on_provider(map_id, &provider_name.to_string())
+            .await?;
+        if let Some(version) = version {
+            let kind = provider.kind().kind;
+            if !kind.is_versioned() {
+                return Err(Error::Message(format!(
+                    "provider '{provider_name}' ({kind}) does not keep versions"
+                )));
+            }
+            let pm = PathMap {
+                version: Some(version.to_string()),
+                ..pm.clone()
+            };
+            return Ok(provider.get(&pm).await?);
+        }
+        Ok(provider.get(pm).await?)
+    }
+
+    /// List the versions a provider keeps for a map, newest first
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the map is unknown or the provider does
+    /// not keep versions
+    pub async fn history(&self, provider_name: &str, map_id: &str) -> Result<Vec<VersionInfo>> {
+        let (provider, pm) = self
+            .get_pathmap_on_provider(map_id, &provider_name.to_string())
+            .await?;
+        Ok(provider.history(pm).await?)
+    }
+
+    /// Remove cached reads of providers configured with a `cache`, or only of
+    /// `provider_name`. Returns the names of the providers cleared.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the provider has no cache, or removal fails
+    pub fn clear_cache(&self, provider_name: Option<&str>) -> Result<Vec<String>> {
+        let mut cleared = Vec::new();
+        for (name, providercfg) in &self.config.providers {
+            if provider_name.is_some_and(|only| only != name) {
+                continue;
+            }
+            if let Some(cache) = &providercfg.cache {
+                teller_providers::cache::clear(&cache.dir(), Some(name))?;
+                cleared.push(name.clone());
+            }
+        }
+        if let (Some(name), true) = (provider_name, cleared.is_empty()) {
+            return Err(Error::Message(format!(
+                "provider '{name}' is not configured with a cache"
+            )));
+        }
+        Ok(cleared)
+    }
+
+    /// List the paths and keys a configured provider holds under `prefix`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the provider is unknown or listing fails
+    pub async fn list(&self, provider_name: &str, prefix: &str) -> Result<Vec<ListEntry>> {
+        let provider = self.registry.get(provider_name).await?.ok_or_else(|| {
+            Error::Message(format!("cannot get initialized provider '{provider_name}'"))
+        })?;
+        Ok(provider.list(prefix).await?)
+    }
+
+    /// Build the list of copies implied by the configuration: a map with
+    /// `source: <name>` is copied into every map declaring `sink: <name>`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a source is declared twice, or a sink
+    /// refers to a source that does not exist
+    pub fn sync_plan(&self) -> Result<Vec<SyncPair>> {
+        let mut sources: BTreeMap<&str, (&String, &PathMap)> = BTreeMap::new();
+        for (name, providercfg) in &self.config.providers {
+            for pm in &providercfg.maps {
+                if let Some(source) = &pm.source {
+                    if let Some((other, other_pm)) = sources.insert(source, (name, pm)) {
+                        return Err(Error::Message(format!(
+                            "source '{source}' is declared by both '{other}/{}' and '{name}/{}'",
+                            other_pm.id, pm.id
+                        )));
+                    }
+                }
+            }
+        }
+
+        let mut plan = Vec::new();
+        for (name, providercfg) in &self.config.providers {
+            for pm in &providercfg.maps {
+                if let Some(sink) = &pm.sink {
+                    let (from_provider, from_pm) = sources.get(sink.as_str()).ok_or_else(|| {
+                        Error::Message(format!(
+                            "sink '{name}/{}' refers to an unknown source '{sink}'",
+                            pm.id
+                        ))
+                    })?;
+                    plan.push(SyncPair {
+                        from_provider: (*from_provider).clone(),
+                        from_map_id: from_pm.id.clone(),
+                        to_provider: name.clone(),
+                        to_map_id: pm.id.clone(),
+                    });
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Copy every source map into its declared sinks, see [`Teller::sync_plan`].
+    /// Note: `replace` will first delete data at each sink, then copy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if planning or any of the copies fails
+    pub async fn sync(&self, replace: bool) -> Result<Vec<SyncPair>> {
+        let plan = self.sync_plan()?;
+        for pair in &plan {
+            self.copy(
+                &pair.from_provider,
+                &pair.from_map_id,
+                &pair.to_provider,
+                &pair.to_map_id,
+                replace,
+            )
+            .await?;
+        }
+        Ok(plan)
+    }
+}
+
+fn same_values(a: &[KV], b: &[KV]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.key == b.key && a.value == b.value)
+}
+
+/// The values that lost to others of the same key
+fn shadowed_values(shadowed: Vec<Shadowed>) -> Vec<KV> {
+    shadowed.into_iter().map(|s| s.kv).collect()
+}
+
+/// Keys whose change a signaled command cannot see: environment variables it was started
+/// with that changed or went away, new environment variables, and files added after it
+/// started, which it has no `_FILE` variable for
+fn unseen_changes(
+    started_env: &[KV],
+    started_files: &[KV],
+    env: &[KV],
+    files: &[KV],
+) -> Vec<String> {
+    let mut unseen = env
+        .iter()
+        .filter(|kv| {
+            !started_env
+                .iter()
+                .any(|s| s.key == kv.key && s.value == kv.value)
+        })
+        .chain(
+            started_env
+                .iter()
+                .filter(|s| !env.iter().any(|kv| kv.key == s.key)),
+        )
+        .chain(
+            files
+                .iter()
+                .filter(|kv| !started_files.iter().any(|s| s.key == kv.key)),
+        )
+        .map(|kv| kv.key.clone())
+        .collect::<Vec<_>>();
+    unseen.sort();
+    unseen.dedup();
+    unseen
+}
+
+/// Where a collected key comes from, as `<provider>/<map id>`
+#[must_use]
+pub fn origin(kv: &KV) -> String {
+    format!(
+        "{}/{}",
+        kv.provider.as_ref().map_or("n/a", |p| p.name.as_str()),
+        kv.path.as_ref().map_or("n/a", |p| p.id.as_str())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OptionalPolicy;
+
+    const CONFIG: &str = r"
+concurrency: 2
+providers:
+  mem_a:
+    kind: inmem
+    options:
+      one: { A: '1' }
+      two: { B: '2' }
+      three: { C: '3' }
+    maps:
+      - id: one
+        path: one
+      - id: two
+        path: two
+      - id: three
+        path: three
+  mem_b:
+    kind: inmem
+    options:
+      four: { D: '4' }
+    maps:
+      - id: four
+        path: four
+";
+
+    #[tokio::test]
+    async fn collect_keeps_config_order() {
+        let teller = Teller::from_config(&Config::from_text(CONFIG).unwrap())
+            .await
+            .unwrap();
+        let kvs = teller.collect().await.unwrap();
+        assert_eq!(
+            kvs.iter().map(|kv| kv.key.as_str()).collect::<Vec<_>>(),
+            vec!["A", "B", "C", "D"]
+        );
+    }
+
+    const OPTIONAL_CONFIG: &str = r"
+providers:
+  mem:
+    kind: inmem
+    options:
+      one: { A: '1' }
+    maps:
+      - id: one
+        path: one
+      - id: missing
+        path: missing
+        optional: true
+";
+
+    #[tokio::test]
+    async fn collect_skips_optional_maps() {
+        let teller = Teller::from_config(&Config::from_text(OPTIONAL_CONFIG).unwrap())
+            .await
+            .unwrap();
+        let collected = teller.collect_with_report().await.unwrap();
+        assert_eq!(collected.kvs.len(), 1);
+        assert_eq!(collected.skipped.len(), 1);
+        assert_eq!(collected.skipped[0].provider, "mem");
+        assert_eq!(collected.skipped[0].map_id, "missing");
+
+        let strict = OPTIONAL_CONFIG.replace("optional: true", "optional: false");
+        let teller = Teller::from_config(&Config::from_text(&strict).unwrap())
+            .await
+            .unwrap();
+        assert!(teller.collect().await.is_err());
+    }
+
+    const SYNC_CONFIG: &str = r"
+providers:
+  canonical:
+    kind: inmem
+    options:
+      app: { DB_USER: linus }
+    maps:
+      - id: app
+        path: app
+        source: app
+  mirror:
+    kind: inmem
+    maps:
+      - id: app
+        path: mirrored/app
+        sink: app
+";
+
+    #[tokio::test]
+    async fn sync_copies_sources_into_sinks() {
+        let teller = Teller::from_config(&Config::from_text(SYNC_CONFIG).unwrap())
+            .await
+            .unwrap();
+        let plan = teller.sync(false).await.unwrap();
+        assert_eq!(
+            plan.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["canonical/app -> mirror/app"]
+        );
+
+        let (mirror, pm) = teller
+            .get_pathmap_on_provider("app", &"mirror".to_string())
+            .await
+            .unwrap();
+        let kvs = mirror.get(pm).await.unwrap();
+        assert_eq!(kvs[0].key, "DB_USER");
+        assert_eq!(kvs[0].value, "linus");
+
+        let dangling = SYNC_CONFIG.replace("sink: app", "sink: other");
+        let teller = Teller::from_config(&Config::from_text(&dangling).unwrap())
+            .await
+            .unwrap();
+        assert!(teller.sync_plan().is_err());
+    }
+
+    #[test]
+    fn optional_policy_skips() {
+        let not_found = teller_providers::Error::NotFound {
+            path: "p".to_string(),
+            msg: "not found".to_string(),
+        };
+        let get_error = teller_providers::Error::GetError {
+            path: "p".to_string(),
+            msg: "denied".to_string(),
+        };
+        assert!(OptionalPolicy::NotFound.skips(&not_found));
+        assert!(!OptionalPolicy::NotFound.skips(&get_error));
+        assert!(OptionalPolicy::AnyError.skips(&get_error));
+    }
+
+    #[tokio::test]
+    async fn version_pins_need_versioned_providers() {
+        let config = Config::from_text(
+            r"
+providers:
+  mem:
+    kind: inmem
+    options:
+      one: { A: '1' }
+    maps:
+      - id: one
+        path: one
+        version: '3'
+",
+        )
+        .unwrap();
+        let err = Teller::from_config(&config).await.err().unwrap();
+        assert!(err.to_string().contains("does not keep versions"));
+    }
+
+    #[tokio::test]
+    async fn custom_kinds_use_registered_factories() {
+        use teller_providers::providers::inmem::Inmem;
+
+        let config = Config::from_text(
+            r"
+providers:
+  acme:
+    kind: acme_vault
+    options:
+      app: { TOKEN: 'from-factory' }
+    maps:
+      - id: app
+        path: app
+  fixed:
+    kind: inmem
+    maps:
+      - id: app
+        path: app
+",
+        )
+        .unwrap();
+
+        let err = Teller::from_config(&config).await.err().unwrap();
+        assert!(err.to_string().contains("unknown kind 'acme_vault'"));
+
+        let registry = RegistryBuilder::new()
+            .with_factory("acme_vault", |name, opts| {
+                Ok(Box::new(Inmem::new(name, opts)?))
+            })
+            .with_provider(
+                "fixed",
+                Box::new(Inmem::from_yaml("fixed", "app: { OTHER: 'instance' }").unwrap()),
+            );
+        let teller = Teller::from_config_with_registry(&config, registry)
+            .await
+            .unwrap();
+        let kvs = teller.collect().await.unwrap();
+        assert_eq!(
+            kvs.iter()
+                .map(|kv| (kv.key.as_str(), kv.value.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("TOKEN", "from-factory"), ("OTHER", "instance")]
+        );
+    }
+
+    /// Run `script` watching an inmem map, change its value once the script logged the
+    /// first one, and return the log
+    #[cfg(unix)]
+    async fn watched_run(script: &str, on_change: exec::OnChange, files: bool) -> String {
+        let config = Config::from_text(
+            r"
+providers:
+  mem:
+    kind: inmem
+    options:
+      app: { VAL: '1' }
+    maps:
+      - id: app
+        path: app
+",
+        )
+        .unwrap();
+        let teller = Teller::from_config(&config).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let opts = exec::Opts {
+            pwd: dir.path(),
+            capture: false,
+            sh: false,
+            reset_env: false,
+            redact: false,
+            env: exec::ExecConfig::default(),
+            files_dir: files.then_some(dir.path()),
+            watch: Some(exec::Watch {
+                interval: Duration::from_millis(100),
+                on_change,
+            }),
+        };
+        let change = async {
+            while !std::fs::read_to_string(&log).is_ok_and(|l| l.ends_with('\n')) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            teller
+                .put(&[KV::from_kv("VAL", "2")], "app", &["mem".to_string()])
+                .await
+                .unwrap();
+        };
+        let (output, ()) = tokio::time::timeout(
+            Duration::from_secs(20),
+            futures::future::join(teller.run(&["sh", "-c", script], &opts), change),
+        )
+        .await
+        .unwrap();
+        assert!(output.unwrap().status.success());
+        std::fs::read_to_string(&log).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn watch_restarts_the_command_on_change() {
+        let log = watched_run(
+            r#"echo "$VAL" >> log; [ "$VAL" = 2 ] || exec sleep 10"#,
+            exec::OnChange::Restart,
+            false,
+        )
+        .await;
+        assert_eq!(log, "1\n2\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn watch_signals_the_command_on_change() {
+        let log = watched_run(
+            r#"trap 'cat "$VAL_FILE" >> log; echo >> log; exit 0' HUP
+               cat "$VAL_FILE" >> log; echo >> log
+               while :; do sleep 0.05; done"#,
+            exec::OnChange::Signal(libc::SIGHUP),
+            true,
+        )
+        .await;
+        assert_eq!(log, "1\n2\n");
+    }
+
+    #[tokio::test]
+    async fn signaling_needs_files() {
+        let teller = Teller::from_config(&Config::from_text(CONFIG).unwrap())
+            .await
+            .unwrap();
+        let opts = exec::Opts {
+            pwd: Path::new("."),
+            capture: false,
+            sh: true,
+            reset_env: false,
+            redact: false,
+            env: exec::ExecConfig::default(),
+            files_dir: None,
+            watch: Some(exec::Watch {
+                interval: Duration::from_secs(1),
+                on_change: exec::OnChange::Signal(1),
+            }),
+        };
+        assert!(teller.run(&["true"], &opts).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn providers_load_on_first_use() {
+        let config = Config::from_text(
+            r"
+providers:
+  mem:
+    kind: inmem
+    maps:
+      - id: app
+        path: app
+  remote:
+    kind: acme_remote
+    maps:
+      - id: app
+        path: app
+",
+        )
+        .unwrap();
+        let registry = RegistryBuilder::new().with_factory("acme_remote", |_, _| {
+            Err(teller_providers::Error::CreateProviderError(
+                "no credentials".to_string(),
+            ))
+        });
+        let teller = Teller::from_config_with_registry(&config, registry)
+            .await
+            .unwrap();
+
+        // only the providers touched by a command need to load
+        teller
+            .put(&[KV::from_kv("A", "1")], "app", &["mem".to_string()])
+            .await
+            .unwrap();
+        let err = teller.collect().await.err().unwrap();
+        assert!(err.to_string().contains("no credentials"));
+    }
+
+    #[tokio::test]
+    async fn collect_reads_selected_maps() {
+        let config = Config::from_text(&CONFIG.replace(
+            "      - id: two\n        path: two\n",
+            "      - id: two\n        path: two\n        tags: [backend]\n",
+        ))
+        .unwrap();
+        let keys = |selection: Selection| {
+            let config = &config;
+            async move {
+                let teller = Teller::from_config(config)
+                    .await
+                    .unwrap()
+                    .with_selection(selection)
+                    .unwrap();
+                teller
+                    .collect()
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|kv| kv.key)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let selection = |providers: &[&str], map_ids: &[&str], tags: &[&str], exclude: &[&str]| {
+            let strings = |v: &[&str]| v.iter().map(ToString::to_string).collect();
+            Selection {
+                providers: strings(providers),
+                map_ids: strings(map_ids),
+                tags: strings(tags),
+                exclude_maps: strings(exclude),
+            }
+        };
+
+        assert_eq!(keys(selection(&["mem_b"], &[], &[], &[])).await, vec!["D"]);
+        assert_eq!(
+            keys(selection(&[], &["one", "mem_b/four"], &[], &[])).await,
+            vec!["A", "D"]
+        );
+        assert_eq!(
+            keys(selection(&[], &[], &["backend"], &[])).await,
+            vec!["B"]
+        );
+        assert_eq!(
+            keys(selection(&["mem_a"], &[], &[], &["two", "mem_a/three"])).await,
+            vec!["A"]
+        );
+
+        let teller = Teller::from_config(&config).await.unwrap();
+        let err = teller
+            .with_selection(selection(&["nope"], &[], &[], &[]))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "cannot find provider 'nope'");
+        let teller = Teller::from_config(&config).await.unwrap();
+        assert!(teller
+            .with_selection(selection(&[], &["four"], &["backend"], &[]))
+            .is_err());
+    }
+
+    const CONFLICTS: &str = r"
+providers:
+  vault:
+    kind: inmem
+    priority: 10
+    options:
+      app: { DB_PASS: 'vault', TOKEN: 'abc' }
+    maps:
+      - id: app
+        path: app
+  local:
+    kind: inmem
+    options:
+      app: { DB_PASS: 'stale', TOKEN: 'local', PORT: '5432' }
+      override: { PORT: '6543' }
+    maps:
+      - id: app
+        path: app
+      - id: override
+        path: override
+";
+
+    #[tokio::test]
+    async fn resolves_key_conflicts_by_priority() {
+        let config = Config::from_text(CONFLICTS).unwrap();
+        let teller = Teller::from_config(&config).await.unwrap();
+        let collected = teller.collect_with_report().await.unwrap();
+        assert_eq!(
+            collected
+                .kvs
+                .iter()
+                .map(|kv| (kv.key.as_str(), kv.value.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("PORT", "6543"), ("DB_PASS", "vault"), ("TOKEN", "abc")]
+        );
+        assert_eq!(
+            collected
+                .shadowed
+                .iter()
+                .map(|s| (s.kv.value.as_str(), s.by.value.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("stale", "vault"), ("5432", "6543"), ("local", "abc")]
+        );
+
+        // equal priorities are ambiguous in strict mode, different ones are not
+        let err = Teller::from_config(&config)
+            .await
+            .unwrap()
+            .with_strict(true)
+            .collect()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "duplicate key 'PORT' in local/app and local/override, give one of them a higher \
+             priority"
+        );
+    }
 }
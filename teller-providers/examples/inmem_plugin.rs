@@ -0,0 +1,19 @@
+//! A reference teller plugin, serving an in-memory store over stdin/stdout.
+//!
+//! Build it with `cargo build --example inmem_plugin`, then point a provider at it:
+//!
+//! ```yaml
+//! providers:
+//!  mem1:
+//!    kind: plugin
+//!    options:
+//!      command: target/debug/examples/inmem_plugin
+//! ```
+use teller_providers::providers::{inmem::Inmem, plugin};
+use tokio::io::{stdin, stdout, BufReader};
+
+#[tokio::main]
+async fn main() -> teller_providers::Result<()> {
+    let store = Inmem::new("inmem_plugin", None)?;
+    plugin::serve(&store, "inmem", BufReader::new(stdin()), stdout()).await
+}
@@ -0,0 +1,519 @@
+//! Encrypted on-disk cache for provider reads
+//!
+//! ## Example configuration
+//!
+//! ```yaml
+//! providers:
+//!  vault1:
+//!    kind: hashicorp
+//!    cache:
+//!      ttl: 10m
+//!      allow_stale: true  # serve expired entries when the provider fails
+//!    maps: ...
+//! ```
+//!
+//! Entries are encrypted with ChaCha20-Poly1305. The key is a random key file created for the
+//! OS user (readable only by them) in the cache folder, or, when `TELLER_CACHE_PASSPHRASE` is
+//! set, derived from that passphrase.
+#![allow(clippy::borrowed_box)]
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use fs_err as fs;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    digest, pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::{ListEntry, PathMap, ProviderInfo, VersionInfo, KV},
+    providers::ProviderKind,
+    Error, Provider, Result,
+};
+
+/// Environment variable holding a passphrase to derive the cache key from
+pub const PASSPHRASE_ENV: &str = "TELLER_CACHE_PASSPHRASE";
+const KEY_FILE: &str = "key";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct CacheCfg {
+    /// How long a read is served from cache, e.g. `10m`, `1h`
+    #[serde(with = "humantime_serde")]
+    pub ttl: Duration,
+    /// Serve expired entries, with a warning, when the provider fails
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub allow_stale: bool,
+    /// Cache folder, defaults to [`default_dir`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+impl CacheCfg {
+    #[must_use]
+    pub fn dir(&self) -> PathBuf {
+        self.dir.clone().unwrap_or_else(default_dir)
+    }
+}
+
+/// `$XDG_CACHE_HOME/teller`, or `~/.cache/teller`
+#[must_use]
+pub fn default_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home::home_dir().map(|home| home.join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("teller")
+}
+
+/// Remove cached entries of a provider, or of every provider when `provider_name` is none
+///
+/// # Errors
+///
+/// This function will return an error if the entries cannot be removed
+pub fn clear(dir: &Path, provider_name: Option<&str>) -> Result<()> {
+    let target = provider_name.map_or_else(|| dir.to_path_buf(), |name| provider_dir(dir, name));
+    match fs::remove_dir_all(&target) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    digest::digest(&digest::SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn provider_dir(dir: &Path, provider_name: &str) -> PathBuf {
+    dir.join(hex_digest(provider_name.as_bytes()))
+}
+
+fn cache_err(msg: impl std::fmt::Display) -> Error {
+    Error::Message(format!("cache: {msg}"))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    stored_at: u64,
+    kvs: Vec<KV>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+const KEY_LEN: usize = 32;
+
+/// Load the user's key file from the cache folder, creating it when missing. The key is
+/// written to a file of its own and linked into place, so concurrent runs agree on a single
+/// complete key.
+fn key_material(dir: &Path) -> Result<Vec<u8>> {
+    let path = dir.join(KEY_FILE);
+    match fs::read(&path) {
+        Ok(key) => return checked_key(&path, key),
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+        Err(_) => {}
+    }
+
+    let mut key = vec![0u8; KEY_LEN];
+    SystemRandom::new().fill(&mut key).map_err(cache_err)?;
+    fs::create_dir_all(dir)?;
+    let mut suffix = [0u8; 8];
+    SystemRandom::new().fill(&mut suffix).map_err(cache_err)?;
+    let staged = dir.join(format!("{KEY_FILE}.{}", hex_digest(&suffix)));
+    {
+        use std::io::Write;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        fs_err::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&staged)?;
+        file.write_all(&key)?;
+        file.sync_all()?;
+    }
+    let linked = fs::hard_link(&staged, &path);
+    fs::remove_file(&staged)?;
+    match linked {
+        Ok(()) => Ok(key),
+        // another run got there first, its key is complete once linked
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            checked_key(&path, fs::read(&path)?)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn checked_key(path: &Path, key: Vec<u8>) -> Result<Vec<u8>> {
+    if key.len() == KEY_LEN {
+        Ok(key)
+    } else {
+        Err(cache_err(format!(
+            "key file '{}' does not hold a {KEY_LEN} byte key, remove it to start a new cache",
+            path.display()
+        )))
+    }
+}
+
+fn cache_key(dir: &Path) -> Result<LessSafeKey> {
+    let material = key_material(dir)?;
+    let mut key = [0u8; 32];
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ITERATIONS).expect("non zero"),
+            &material,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+    } else {
+        key.copy_from_slice(digest::digest(&digest::SHA256, &material).as_ref());
+    }
+    Ok(LessSafeKey::new(
+        UnboundKey::new(&CHACHA20_POLY1305, &key).map_err(cache_err)?,
+    ))
+}
+
+/// A provider wrapper serving `get` from an encrypted on-disk cache
+pub struct Cached {
+    inner: Box<dyn Provider + Send + Sync>,
+    name: String,
+    /// Identifies the backend behind `name`, so same-named providers of different
+    /// configurations never share entries
+    backend: String,
+    cfg: CacheCfg,
+    key: LessSafeKey,
+}
+
+impl Cached {
+    /// Wrap a provider with a cache
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache key cannot be loaded or created
+    pub fn new(name: &str, inner: Box<dyn Provider + Send + Sync>, cfg: &CacheCfg) -> Result<Self> {
+        Ok(Self {
+            key: cache_key(&cfg.dir())?,
+            inner,
+            name: name.to_string(),
+            backend: String::new(),
+            cfg: cfg.clone(),
+        })
+    }
+
+    /// Scope entries to the backend a provider is configured for: its kind and options
+    #[must_use]
+    pub fn for_backend(mut self, kind: &ProviderKind, options: Option<&serde_json::Value>) -> Self {
+        self.backend = hex_digest(&serde_json::to_vec(&(kind, options)).unwrap_or_default());
+        self
+    }
+
+    fn entry_path(&self, pm: &PathMap) -> Result<PathBuf> {
+        let id = serde_json::to_vec(&(&self.backend, pm))?;
+        Ok(provider_dir(&self.cfg.dir(), &self.name).join(hex_digest(&id)))
+    }
+
+    /// Read an entry, `None` when missing or unreadable (e.g. encrypted with another key)
+    fn read(&self, pm: &PathMap) -> Option<Entry> {
+        let raw = fs::read(self.entry_path(pm).ok()?).ok()?;
+        if raw.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = raw.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut sealed = sealed.to_vec();
+        let plain = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .ok()?;
+        serde_json::from_slice(plain).ok()
+    }
+
+    fn write(&self, pm: &PathMap, kvs: &[KV]) -> Result<()> {
+        let mut data = serde_json::to_vec(&Entry {
+            stored_at: now(),
+            kvs: kvs.to_vec(),
+        })?;
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).map_err(cache_err)?;
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(cache_err)?;
+
+        let path = self.entry_path(pm)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, [nonce.as_slice(), &data].concat())?;
+        Ok(())
+    }
+
+    fn invalidate(&self) -> Result<()> {
+        clear(&self.cfg.dir(), Some(&self.name))
+    }
+
+    /// Drop the entry of a single map
+    fn forget(&self, pm: &PathMap) -> Result<()> {
+        match fs::remove_file(self.entry_path(pm)?) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for Cached {
+    fn kind(&self) -> ProviderInfo {
+        self.inner.kind()
+    }
+
+    async fn get(&self, pm: &PathMap) -> Result<Vec<KV>> {
+        let cached = self.read(pm);
+        if let Some(entry) = &cached {
+            if now().saturating_sub(entry.stored_at) < self.cfg.ttl.as_secs() {
+                return Ok(entry.kvs.clone());
+            }
+        }
+
+        match self.inner.get(pm).await {
+            Ok(kvs) => {
+                self.write(pm, &kvs)?;
+                Ok(kvs)
+            }
+            Err(err @ Error::NotFound { .. }) => Err(err),
+            Err(err) => match cached {
+                Some(entry) if self.cfg.allow_stale => {
+                    tracing::warn!(
+                        provider = self.name.as_str(),
+                        path = pm.path.as_str(),
+                        age_secs = now().saturating_sub(entry.stored_at),
+                        error = %err,
+                        "serving stale cache"
+                    );
+                    Ok(entry.kvs)
+                }
+                _ => Err(err),
+            },
+        }
+    }
+
+    async fn put(&self, pm: &PathMap, kvs: &[KV]) -> Result<()> {
+        let res = self.inner.put(pm, kvs).await;
+        self.invalidate()?;
+        res
+    }
+
+    async fn del(&self, pm: &PathMap) -> Result<()> {
+        let res = self.inner.del(pm).await;
+        self.invalidate()?;
+        res
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn history(&self, pm: &PathMap) -> Result<Vec<VersionInfo>> {
+        self.inner.history(pm).await
+    }
+
+    async fn watch(&self, pm: &PathMap, timeout: Duration) -> Result<bool> {
+        let changed = self.inner.watch(pm, timeout).await?;
+        // whoever watches wants the next read to see a reported change
+        if changed {
+            self.forget(pm)?;
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{CacheCfg, Cached};
+    use crate::{
+        config::{PathMap, ProviderInfo, KV},
+        providers::{inmem::Inmem, ProviderKind},
+        Error, Provider, Result,
+    };
+
+    /// An inmem store that can be taken offline, and reports changes to `app` when watched
+    struct Flaky {
+        inner: Inmem,
+        offline: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Provider for Flaky {
+        fn kind(&self) -> ProviderInfo {
+            self.inner.kind()
+        }
+        async fn get(&self, pm: &PathMap) -> Result<Vec<KV>> {
+            if self.offline.load(Ordering::SeqCst) {
+                return Err(Error::Message("connection refused".to_string()));
+            }
+            self.inner.get(pm).await
+        }
+        async fn put(&self, pm: &PathMap, kvs: &[KV]) -> Result<()> {
+            self.inner.put(pm, kvs).await
+        }
+        async fn del(&self, pm: &PathMap) -> Result<()> {
+            self.inner.del(pm).await
+        }
+        async fn watch(&self, pm: &PathMap, _timeout: Duration) -> Result<bool> {
+            Ok(pm.path == "app")
+        }
+    }
+
+    fn cached(
+        dir: &std::path::Path,
+        ttl: Duration,
+        allow_stale: bool,
+    ) -> (Cached, Arc<AtomicBool>) {
+        let offline = Arc::new(AtomicBool::new(false));
+        let flaky = Flaky {
+            inner: Inmem::from_yaml("mem", "{ app: { TOKEN: 'v1' }, other: { TOKEN: 'o1' } }")
+                .unwrap(),
+            offline: offline.clone(),
+        };
+        let cfg = CacheCfg {
+            ttl,
+            allow_stale,
+            dir: Some(dir.to_path_buf()),
+        };
+        (Cached::new("mem", Box::new(flaky), &cfg).unwrap(), offline)
+    }
+
+    #[tokio::test]
+    async fn serves_fresh_entries_and_invalidates_on_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let (p, offline) = cached(dir.path(), Duration::from_secs(600), false);
+        let pm = PathMap::from_path("app");
+
+        assert_eq!(p.get(&pm).await.unwrap()[0].value, "v1");
+        offline.store(true, Ordering::SeqCst);
+        assert_eq!(p.get(&pm).await.unwrap()[0].value, "v1");
+
+        // entries are not stored in the clear
+        let key_file = dir.path().join(super::KEY_FILE);
+        for entry in walk(dir.path()) {
+            if entry != key_file {
+                let raw = std::fs::read(&entry).unwrap();
+                assert!(!String::from_utf8_lossy(&raw).contains("TOKEN"));
+            }
+        }
+
+        offline.store(false, Ordering::SeqCst);
+        p.put(&pm, &[KV::from_kv("TOKEN", "v2")]).await.unwrap();
+        assert_eq!(p.get(&pm).await.unwrap()[0].value, "v2");
+    }
+
+    #[test]
+    fn concurrent_runs_share_one_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = std::thread::scope(|scope| {
+            let runs = (0..8)
+                .map(|_| scope.spawn(|| super::key_material(dir.path()).unwrap()))
+                .collect::<Vec<_>>();
+            runs.into_iter()
+                .map(|run| run.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert!(keys.iter().all(|key| *key == keys[0]));
+        // only the key file is left
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        std::fs::write(dir.path().join(super::KEY_FILE), b"short").unwrap();
+        assert!(super::key_material(dir.path()).is_err());
+    }
+
+    #[tokio::test]
+    async fn forgets_only_entries_reported_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (p, offline) = cached(dir.path(), Duration::from_secs(600), false);
+        let (app, other) = (PathMap::from_path("app"), PathMap::from_path("other"));
+        p.get(&app).await.unwrap();
+        p.get(&other).await.unwrap();
+        offline.store(true, Ordering::SeqCst);
+
+        // nothing reported, entries stay
+        assert!(!p.watch(&other, Duration::ZERO).await.unwrap());
+        assert!(p.get(&app).await.is_ok());
+
+        assert!(p.watch(&app, Duration::ZERO).await.unwrap());
+        assert!(p.get(&app).await.is_err());
+        assert_eq!(p.get(&other).await.unwrap()[0].value, "o1");
+    }
+
+    #[tokio::test]
+    async fn scopes_entries_to_the_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let pm = PathMap::from_path("app");
+        let (p, _) = cached(dir.path(), Duration::from_secs(600), false);
+        let p = p.for_backend(
+            &ProviderKind::Inmem,
+            Some(&serde_json::json!({ "address": "https://a" })),
+        );
+        p.get(&pm).await.unwrap();
+
+        // the same provider name, pointing elsewhere
+        let (other, offline) = cached(dir.path(), Duration::from_secs(600), false);
+        let other = other.for_backend(
+            &ProviderKind::Inmem,
+            Some(&serde_json::json!({ "address": "https://b" })),
+        );
+        offline.store(true, Ordering::SeqCst);
+        assert!(other.get(&pm).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stale_entries_need_allow_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let pm = PathMap::from_path("app");
+
+        let (p, offline) = cached(dir.path(), Duration::ZERO, false);
+        p.get(&pm).await.unwrap();
+        offline.store(true, Ordering::SeqCst);
+        assert!(p.get(&pm).await.is_err());
+
+        let (p, offline) = cached(dir.path(), Duration::ZERO, true);
+        offline.store(true, Ordering::SeqCst);
+        assert_eq!(p.get(&pm).await.unwrap()[0].value, "v1");
+    }
+
+    fn walk(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .flat_map(|entry| {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    walk(&path)
+                } else {
+                    vec![path]
+                }
+            })
+            .collect()
+    }
+}
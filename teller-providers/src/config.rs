@@ -1,9 +1,11 @@
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 
+use heck::{ToKebabCase, ToLowerCamelCase, ToShoutySnakeCase, ToSnakeCase};
+use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
 
-use crate::providers::ProviderKind;
+use crate::{cache::CacheCfg, format::ValueFormat, providers::ProviderKind, retry::RetryCfg};
 
 fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
@@ -17,10 +19,17 @@ pub struct ProviderCfg {
     pub options: Option<serde_json::Value>,
     #[serde(rename = "name", skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    #[serde(default, rename = "cache", skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheCfg>,
+    #[serde(default, rename = "retry", skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryCfg>,
+    // keys from providers with a higher priority win over the same keys from others
+    #[serde(default, rename = "priority", skip_serializing_if = "is_default")]
+    pub priority: i32,
     pub maps: Vec<PathMap>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, PartialOrd, Ord)]
 pub enum Sensitivity {
     #[default]
     None,
@@ -30,6 +39,97 @@ pub enum Sensitivity {
     Critical,
 }
 
+/// How a command started by `teller run` receives the keys of a map
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Delivery {
+    /// As environment variables
+    #[default]
+    #[serde(rename = "env")]
+    Env,
+    /// As files holding the values, pointed to by `KEY_FILE` environment variables
+    #[serde(rename = "file")]
+    File,
+}
+
+/// An entry found when listing a provider location
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub enum ListEntry {
+    /// A child location, usable as a map `path`
+    #[serde(rename = "path")]
+    Path(String),
+    /// A key stored directly under the listed location
+    #[serde(rename = "key")]
+    Key(String),
+}
+
+/// A stored version of a secret, as reported by a provider that keeps history
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct VersionInfo {
+    /// The key this version belongs to, for providers that version each key on its own
+    /// (none when the whole path is versioned)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub version: String,
+    /// Creation time, RFC 3339
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// This is the version served when no `version` is pinned
+    pub current: bool,
+    /// The version was deleted, destroyed or deprecated and may not be readable
+    pub deleted: bool,
+}
+
+/// Case conversion for key names read from a map
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum KeyTransform {
+    /// `db/password` becomes `DB_PASSWORD`
+    #[serde(rename = "upper_snake")]
+    UpperSnake,
+    /// `db/password` becomes `db_password`
+    #[serde(rename = "lower_snake")]
+    LowerSnake,
+    /// `db/password` becomes `dbPassword`
+    #[serde(rename = "camel")]
+    Camel,
+    /// `db/password` becomes `db-password`
+    #[serde(rename = "kebab")]
+    Kebab,
+}
+
+impl KeyTransform {
+    #[must_use]
+    pub fn apply(&self, key: &str) -> String {
+        match self {
+            Self::UpperSnake => key.to_shouty_snake_case(),
+            Self::LowerSnake => key.to_snake_case(),
+            Self::Camel => key.to_lower_camel_case(),
+            Self::Kebab => key.to_kebab_case(),
+        }
+    }
+}
+
+/// A rename rule for key names read from a map. `replace` can refer to capture groups of
+/// `pattern`, as in `$1`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyRename {
+    #[serde(with = "regex_str")]
+    pub pattern: Regex,
+    pub replace: String,
+}
+
+mod regex_str {
+    use regex::Regex;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(re: &Regex, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(re.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+        Regex::new(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
 pub struct ProviderInfo {
     pub kind: ProviderKind,
@@ -120,7 +220,7 @@ impl KV {
     ) -> Self {
         Self {
             value: found_val.to_string(),
-            key: to_key.to_string(),
+            key: pm.transform_key(to_key),
             from_key: from_key.to_string(),
             path: Some(PathInfo {
                 path: pm.path.clone(),
@@ -170,6 +270,9 @@ pub struct PathMap {
     pub protocol: Option<String>,
     #[serde(rename = "path")]
     pub path: String,
+    // pin reads to a specific version, for providers that keep history
+    #[serde(default, rename = "version", skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
     #[serde(default, rename = "keys", skip_serializing_if = "is_default")]
     pub keys: BTreeMap<String, String>,
     #[serde(default, rename = "decrypt", skip_serializing_if = "is_default")]
@@ -189,9 +292,70 @@ pub struct PathMap {
     // ignore population if optional + we got error
     #[serde(default, rename = "optional", skip_serializing_if = "is_default")]
     pub optional: bool,
+    // overrides the provider priority for keys of this map
+    #[serde(default, rename = "priority", skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    // labels for selecting groups of maps
+    #[serde(default, rename = "tags", skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // rename rules for read keys, applied in order after `keys`
+    #[serde(default, rename = "key_rename", skip_serializing_if = "Vec::is_empty")]
+    pub key_rename: Vec<KeyRename>,
+    #[serde(
+        default,
+        rename = "key_transform",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub key_transform: Option<KeyTransform>,
+    #[serde(
+        default,
+        rename = "key_prefix",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub key_prefix: Option<String>,
+    #[serde(
+        default,
+        rename = "key_suffix",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub key_suffix: Option<String>,
+    // parse values as documents and expose their fields as keys
+    #[serde(default, rename = "format", skip_serializing_if = "Option::is_none")]
+    pub format: Option<ValueFormat>,
+    // the key holding the document, when the path holds other values too
+    #[serde(
+        default,
+        rename = "format_key",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub format_key: Option<String>,
+    // how `teller run` hands the keys of this map to the command
+    #[serde(default, rename = "deliver", skip_serializing_if = "is_default")]
+    pub deliver: Delivery,
 }
 
 impl PathMap {
+    /// The name a key read from this map is exposed as: `key_rename` rules in order, then
+    /// `key_transform`, then `key_prefix` and `key_suffix`
+    #[must_use]
+    pub fn transform_key(&self, key: &str) -> String {
+        let mut key = key.to_string();
+        for rule in &self.key_rename {
+            key = rule
+                .pattern
+                .replace_all(&key, rule.replace.as_str())
+                .into_owned();
+        }
+        if let Some(transform) = &self.key_transform {
+            key = transform.apply(&key);
+        }
+        format!(
+            "{}{key}{}",
+            self.key_prefix.as_deref().unwrap_or_default(),
+            self.key_suffix.as_deref().unwrap_or_default()
+        )
+    }
+
     #[must_use]
     pub fn from_path(path: &str) -> Self {
         Self {
@@ -200,3 +364,70 @@ impl PathMap {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(pm: &str, data: &[(&str, &str)]) -> Vec<String> {
+        let pm: PathMap = serde_yaml::from_str(pm).unwrap();
+        let data = data
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        KV::from_data(&data, &pm, &ProviderInfo::default())
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect()
+    }
+
+    #[test]
+    fn transforms_keys() {
+        let data = [("db/password", "1"), ("db/user-name", "2")];
+        assert_eq!(
+            keys("{ id: a, path: a, key_transform: upper_snake }", &data),
+            vec!["DB_PASSWORD", "DB_USER_NAME"]
+        );
+        assert_eq!(
+            keys("{ id: a, path: a, key_transform: camel }", &data),
+            vec!["dbPassword", "dbUserName"]
+        );
+        assert_eq!(
+            keys(
+                "{ id: a, path: a, key_transform: lower_snake, key_prefix: APP_, key_suffix: _V1 }",
+                &data
+            ),
+            vec!["APP_db_password_V1", "APP_db_user_name_V1"]
+        );
+        assert_eq!(
+            keys(
+                r"
+id: a
+path: a
+key_transform: kebab
+key_rename:
+  - pattern: ^db/(.*)$
+    replace: database/$1
+",
+                &data
+            ),
+            vec!["database-password", "database-user-name"]
+        );
+        // applied after `keys`
+        assert_eq!(
+            keys(
+                "{ id: a, path: a, key_transform: upper_snake, keys: { db/password: pass } }",
+                &data
+            ),
+            vec!["PASS"]
+        );
+    }
+
+    #[test]
+    fn invalid_rename_patterns_fail_to_load() {
+        let res = serde_yaml::from_str::<PathMap>(
+            "{ id: a, path: a, key_rename: [{ pattern: '(', replace: x }] }",
+        );
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,546 @@
+//! Structured secret values
+//!
+//! ## Example configuration
+//!
+//! ```yaml
+//! providers:
+//!  ssm1:
+//!    kind: ssm
+//!    maps:
+//!      - id: db
+//!        path: /prod/db
+//!        format: json   # or yaml, dotenv
+//!        keys:
+//!          DB_PASS: ==                # a top-level field
+//!          /replica/host: REPLICA_HOST # a JSON pointer
+//! ```
+//!
+//! Every value read from a map with a `format` is parsed as a document and its fields are
+//! exposed as keys of their own. Writes go the other way: the stored document is read,
+//! the written keys are merged into it, and it is stored again as a single value. Writing
+//! to a path that holds no document yet creates one, named after the last path segment.
+//!
+//! When the path holds plain values next to the document, `format_key` names the key
+//! holding it, and the other values are left alone:
+//!
+//! ```yaml
+//!      - id: db-config
+//!        path: /prod/db
+//!        format: json
+//!        format_key: CONFIG
+//! ```
+#![allow(clippy::borrowed_box)]
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    config::{ListEntry, PathMap, ProviderInfo, VersionInfo, KV},
+    Error, Provider, Result,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueFormat {
+    Json,
+    Yaml,
+    #[cfg(feature = "dotenv")]
+    Dotenv,
+}
+
+impl std::fmt::Display for ValueFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+            #[cfg(feature = "dotenv")]
+            Self::Dotenv => "dotenv",
+        })
+    }
+}
+
+impl ValueFormat {
+    /// Parse a stored value into a document
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the value is not valid in this format
+    pub fn parse(self, text: &str) -> Result<Value> {
+        Ok(match self {
+            Self::Json => serde_json::from_str(text)?,
+            Self::Yaml => serde_yaml::from_str(text)?,
+            #[cfg(feature = "dotenv")]
+            Self::Dotenv => {
+                let mut fields = Map::new();
+                for res in dotenvy::Iter::new(text.as_bytes()) {
+                    let (k, v) = res.map_err(|e| Error::Message(e.to_string()))?;
+                    fields.insert(k, Value::String(v));
+                }
+                Value::Object(fields)
+            }
+        })
+    }
+
+    /// Serialize a document back into a value to store
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the document cannot be represented in this format
+    pub fn render(self, doc: &Value) -> Result<String> {
+        Ok(match self {
+            Self::Json => serde_json::to_string(doc)?,
+            Self::Yaml => serde_yaml::to_string(doc)?,
+            #[cfg(feature = "dotenv")]
+            Self::Dotenv => {
+                let mut out = String::new();
+                for (k, v) in doc.as_object().into_iter().flatten() {
+                    out.push_str(&format!(
+                        "{k}={}\n",
+                        serde_json::to_string(&Value::String(field_text(v)))?
+                    ));
+                }
+                out
+            }
+        })
+    }
+}
+
+/// The text of a field: strings as they are, anything else as JSON
+fn field_text(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+/// Look up a `keys` selector, a JSON pointer when it starts with `/` and a top-level
+/// field otherwise
+fn select<'a>(doc: &'a Value, selector: &str) -> Option<&'a Value> {
+    if selector.starts_with('/') {
+        doc.pointer(selector)
+    } else {
+        doc.get(selector)
+    }
+}
+
+/// Set the field a selector points to, creating missing objects on the way. Values
+/// replacing a non-string keep its type when they parse as JSON.
+fn assign(doc: &mut Value, selector: &str, value: &str) {
+    let tokens = if selector.starts_with('/') {
+        selector
+            .split('/')
+            .skip(1)
+            .map(|t| t.replace("~1", "/").replace("~0", "~"))
+            .collect::<Vec<_>>()
+    } else {
+        vec![selector.to_string()]
+    };
+    let mut node = doc;
+    for token in tokens {
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        node = node
+            .as_object_mut()
+            .expect("just made an object")
+            .entry(token)
+            .or_insert(Value::Null);
+    }
+    *node = match node {
+        Value::String(_) | Value::Null => Value::String(value.to_string()),
+        _ => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+    };
+}
+
+fn unassign(doc: &mut Value, selector: &str) {
+    let (parent, field) = match selector.rsplit_once('/') {
+        Some((parent, field)) if selector.starts_with('/') => {
+            (parent, field.replace("~1", "/").replace("~0", "~"))
+        }
+        _ => ("", selector.to_string()),
+    };
+    if let Some(Value::Object(fields)) = doc.pointer_mut(parent) {
+        fields.remove(&field);
+    }
+}
+
+/// The map as the inner provider sees it: whole documents, without key selection or
+/// transforms, which apply to the fields. Only the `format_key` document when one is set.
+fn raw(pm: &PathMap) -> PathMap {
+    PathMap {
+        keys: pm
+            .format_key
+            .iter()
+            .map(|key| (key.clone(), key.clone()))
+            .collect(),
+        key_rename: vec![],
+        key_transform: None,
+        key_prefix: None,
+        key_suffix: None,
+        format: None,
+        format_key: None,
+        ..pm.clone()
+    }
+}
+
+/// A provider wrapper exploding structured values of maps with a `format` into their fields
+pub struct Formatted {
+    inner: Box<dyn Provider + Send + Sync>,
+}
+
+impl Formatted {
+    #[must_use]
+    pub fn new(inner: Box<dyn Provider + Send + Sync>) -> Self {
+        Self { inner }
+    }
+
+    /// The single document stored for a map, with the key it is stored under, or an empty
+    /// one when there is none yet, named after `format_key` or the last path segment
+    async fn document(&self, pm: &PathMap, format: ValueFormat) -> Result<(String, Value)> {
+        let docs = match self.inner.get(&raw(pm)).await {
+            Err(Error::NotFound { .. }) => vec![],
+            res => res?,
+        };
+        match docs.as_slice() {
+            [] => {
+                let name = pm
+                    .format_key
+                    .as_deref()
+                    .or_else(|| pm.path.rsplit('/').find(|s| !s.is_empty()));
+                Ok((
+                    name.unwrap_or(&pm.path).to_string(),
+                    Value::Object(Map::new()),
+                ))
+            }
+            [doc] => Ok((doc.from_key.clone(), format.parse(&doc.value)?)),
+            _ => Err(Error::Message(format!(
+                "expected a single {format} document at '{}', found {} values, set `format_key` \
+                 to the key holding it",
+                pm.path,
+                docs.len()
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for Formatted {
+    fn kind(&self) -> ProviderInfo {
+        self.inner.kind()
+    }
+
+    async fn get(&self, pm: &PathMap) -> Result<Vec<KV>> {
+        let Some(format) = pm.format else {
+            return self.inner.get(pm).await;
+        };
+        let provider = self.kind();
+        let mut kvs = Vec::new();
+        for doc in self.inner.get(&raw(pm)).await? {
+            let parsed = format.parse(&doc.value).map_err(|e| Error::GetError {
+                path: pm.path.clone(),
+                msg: format!("'{}' is not a {format} document: {e}", doc.from_key),
+            })?;
+            if pm.keys.is_empty() {
+                for (k, v) in parsed.as_object().into_iter().flatten() {
+                    kvs.push(KV::from_value(&field_text(v), k, k, pm, provider.clone()));
+                }
+            } else {
+                for (selector, to_key) in &pm.keys {
+                    if let Some(v) = select(&parsed, selector) {
+                        kvs.push(KV::from_value(
+                            &field_text(v),
+                            selector,
+                            to_key,
+                            pm,
+                            provider.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(kvs)
+    }
+
+    async fn put(&self, pm: &PathMap, kvs: &[KV]) -> Result<()> {
+        let Some(format) = pm.format else {
+            return self.inner.put(pm, kvs).await;
+        };
+        let (key, mut doc) = self.document(pm, format).await?;
+        for kv in kvs {
+            // keys are written under the name `get` exposes them as
+            let selector = if pm.keys.is_empty() {
+                doc.as_object()
+                    .into_iter()
+                    .flat_map(Map::keys)
+                    .find(|field| pm.transform_key(field) == kv.key)
+                    .cloned()
+            } else {
+                pm.keys
+                    .iter()
+                    .find(|(_, to_key)| pm.transform_key(to_key) == kv.key)
+                    .map(|(selector, _)| selector.clone())
+            };
+            assign(&mut doc, selector.as_deref().unwrap_or(&kv.key), &kv.value);
+        }
+        let value = format.render(&doc)?;
+        self.inner.put(&raw(pm), &[KV::from_kv(&key, &value)]).await
+    }
+
+    async fn del(&self, pm: &PathMap) -> Result<()> {
+        let Some(format) = pm.format else {
+            return self.inner.del(pm).await;
+        };
+        if pm.keys.is_empty() {
+            return self.inner.del(&raw(pm)).await;
+        }
+        let (key, mut doc) = self.document(pm, format).await?;
+        for selector in pm.keys.keys() {
+            unassign(&mut doc, selector);
+        }
+        let value = format.render(&doc)?;
+        self.inner.put(&raw(pm), &[KV::from_kv(&key, &value)]).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn history(&self, pm: &PathMap) -> Result<Vec<VersionInfo>> {
+        self.inner.history(&raw(pm)).await
+    }
+
+    async fn watch(&self, pm: &PathMap, timeout: Duration) -> Result<bool> {
+        self.inner.watch(&raw(pm), timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::providers::inmem::Inmem;
+
+    const DOC: &str =
+        r#"{"DB_PASS": "1234","DB_NAME": "FOO","replica":{"host":"db2","port":5432}}"#;
+
+    fn formatted() -> Formatted {
+        let inmem = Inmem::new("mem", Some(json!({ "db": { "CONFIG": DOC } }))).unwrap();
+        Formatted::new(Box::new(inmem))
+    }
+
+    fn pm(keys: &[(&str, &str)]) -> PathMap {
+        PathMap {
+            format: Some(ValueFormat::Json),
+            keys: keys
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect(),
+            ..PathMap::from_path("db")
+        }
+    }
+
+    fn pairs(kvs: &[KV]) -> Vec<(&str, &str)> {
+        kvs.iter()
+            .map(|kv| (kv.key.as_str(), kv.value.as_str()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn explodes_documents() {
+        let p = formatted();
+        assert_eq!(
+            pairs(&p.get(&pm(&[])).await.unwrap()),
+            vec![
+                ("DB_NAME", "FOO"),
+                ("DB_PASS", "1234"),
+                ("replica", r#"{"host":"db2","port":5432}"#),
+            ]
+        );
+        assert_eq!(
+            pairs(
+                &p.get(&pm(&[("DB_PASS", "PASSWORD"), ("/replica/port", "PORT")]))
+                    .await
+                    .unwrap()
+            ),
+            vec![("PORT", "5432"), ("PASSWORD", "1234")]
+        );
+        // without a format the value is passed through
+        assert_eq!(
+            pairs(&p.get(&PathMap::from_path("db")).await.unwrap()),
+            vec![("CONFIG", DOC)]
+        );
+    }
+
+    #[tokio::test]
+    async fn merges_writes_into_the_document() {
+        let p = formatted();
+        let pm = pm(&[("/replica/port", "PORT")]);
+        p.put(
+            &pm,
+            &[KV::from_kv("PORT", "6543"), KV::from_kv("DB_USER", "admin")],
+        )
+        .await
+        .unwrap();
+        let stored = p.inner.get(&PathMap::from_path("db")).await.unwrap();
+        assert_eq!(
+            ValueFormat::Json.parse(&stored[0].value).unwrap(),
+            json!({
+                "DB_PASS": "1234",
+                "DB_NAME": "FOO",
+                "DB_USER": "admin",
+                "replica": { "host": "db2", "port": 6543 }
+            })
+        );
+
+        p.del(&pm).await.unwrap();
+        let stored = p.inner.get(&PathMap::from_path("db")).await.unwrap();
+        assert_eq!(
+            ValueFormat::Json.parse(&stored[0].value).unwrap()["replica"],
+            json!({ "host": "db2" })
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_keys_by_their_exposed_name() {
+        let p = formatted();
+        let selected = PathMap {
+            key_prefix: Some("APP_".to_string()),
+            ..pm(&[("/replica/host", "HOST")])
+        };
+        p.put(&selected, &[KV::from_kv("APP_HOST", "db3")])
+            .await
+            .unwrap();
+        assert_eq!(
+            pairs(&p.get(&selected).await.unwrap()),
+            vec![("APP_HOST", "db3")]
+        );
+
+        // without `keys`, fields are found by their exposed name too
+        let all = PathMap {
+            key_prefix: Some("APP_".to_string()),
+            ..pm(&[])
+        };
+        p.put(&all, &[KV::from_kv("APP_DB_NAME", "BAR")])
+            .await
+            .unwrap();
+        let stored = p.inner.get(&PathMap::from_path("db")).await.unwrap();
+        assert_eq!(
+            ValueFormat::Json.parse(&stored[0].value).unwrap()["DB_NAME"],
+            json!("BAR")
+        );
+    }
+
+    #[tokio::test]
+    async fn creates_missing_documents() {
+        let p = formatted();
+        let pm = PathMap {
+            path: "new/app".to_string(),
+            ..pm(&[("/replica/host", "HOST")])
+        };
+        p.put(&pm, &[KV::from_kv("HOST", "db3")]).await.unwrap();
+        let stored = p.inner.get(&PathMap::from_path("new/app")).await.unwrap();
+        assert_eq!(stored[0].from_key, "app");
+        assert_eq!(
+            ValueFormat::Json.parse(&stored[0].value).unwrap(),
+            json!({ "replica": { "host": "db3" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn picks_the_document_among_plain_values() {
+        let inmem = Inmem::new(
+            "mem",
+            Some(json!({ "db": { "CONFIG": DOC, "DB_HOST": "db1" } })),
+        )
+        .unwrap();
+        let p = Formatted::new(Box::new(inmem));
+        assert!(p.get(&pm(&[("DB_PASS", "DB_PASS")])).await.is_err());
+        assert!(p.put(&pm(&[]), &[KV::from_kv("A", "1")]).await.is_err());
+
+        let mixed = PathMap {
+            format_key: Some("CONFIG".to_string()),
+            ..pm(&[("DB_PASS", "DB_PASS")])
+        };
+        assert_eq!(
+            pairs(&p.get(&mixed).await.unwrap()),
+            vec![("DB_PASS", "1234")]
+        );
+        p.put(&mixed, &[KV::from_kv("DB_PASS", "5678")])
+            .await
+            .unwrap();
+        p.del(&PathMap {
+            keys: BTreeMap::new(),
+            ..mixed.clone()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            pairs(&p.inner.get(&PathMap::from_path("db")).await.unwrap()),
+            vec![("DB_HOST", "db1")]
+        );
+
+        // writing creates the document under its key
+        p.put(&mixed, &[KV::from_kv("DB_PASS", "9")]).await.unwrap();
+        assert_eq!(
+            pairs(&p.inner.get(&PathMap::from_path("db")).await.unwrap()),
+            vec![("CONFIG", r#"{"DB_PASS":"9"}"#), ("DB_HOST", "db1")]
+        );
+    }
+
+    #[cfg(feature = "dotenv")]
+    #[tokio::test]
+    async fn merges_writes_into_the_stored_document_past_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join("db.env");
+        std::fs::write(&env, format!("CONFIG='{DOC}'\n")).unwrap();
+        let cfg: crate::config::ProviderCfg = serde_yaml::from_str(&format!(
+            "
+kind: dotenv
+cache:
+  ttl: 1h
+  dir: {}
+maps:
+  - id: db
+    path: {}
+    format: json
+",
+            dir.path().join("cache").display(),
+            env.display()
+        ))
+        .unwrap();
+        let registry =
+            crate::registry::Registry::new(&BTreeMap::from([("dot".to_string(), cfg.clone())]))
+                .unwrap();
+        let p = registry.get("dot").await.unwrap().unwrap();
+        p.get(&cfg.maps[0]).await.unwrap();
+
+        // someone else changes the document, then a write merges into it
+        std::fs::write(&env, "CONFIG='{\"DB_PASS\":\"5678\"}'\n").unwrap();
+        p.put(&cfg.maps[0], &[KV::from_kv("DB_USER", "admin")])
+            .await
+            .unwrap();
+        assert_eq!(
+            pairs(&p.get(&cfg.maps[0]).await.unwrap()),
+            vec![("DB_PASS", "5678"), ("DB_USER", "admin")]
+        );
+    }
+
+    #[test]
+    fn round_trips_formats() {
+        let doc = json!({ "A": "1", "B": "two words" });
+        for format in [
+            ValueFormat::Json,
+            ValueFormat::Yaml,
+            #[cfg(feature = "dotenv")]
+            ValueFormat::Dotenv,
+        ] {
+            let text = format.render(&doc).unwrap();
+            assert_eq!(format.parse(&text).unwrap(), doc, "{format}");
+        }
+    }
+}
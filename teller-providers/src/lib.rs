@@ -1,10 +1,15 @@
+pub mod cache;
 pub mod config;
+pub mod format;
 pub mod providers;
 pub mod registry;
+pub mod retry;
+
+use std::time::Duration;
 
 use async_trait::async_trait;
 
-use crate::config::{PathMap, ProviderInfo, KV};
+use crate::config::{ListEntry, PathMap, ProviderInfo, VersionInfo, KV};
 
 #[async_trait]
 pub trait Provider {
@@ -27,6 +32,39 @@ pub trait Provider {
     ///
     /// ...
     async fn del(&self, pm: &PathMap) -> Result<()>;
+    /// List child paths and keys under a prefix
+    ///
+    /// # Errors
+    ///
+    /// ...
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        Err(Error::ListError {
+            path: prefix.to_string(),
+            msg: format!("listing is not supported by '{}'", self.kind().kind),
+        })
+    }
+    /// List the stored versions of a path, newest first
+    ///
+    /// # Errors
+    ///
+    /// ...
+    async fn history(&self, pm: &PathMap) -> Result<Vec<VersionInfo>> {
+        Err(Error::GetError {
+            path: pm.path.clone(),
+            msg: format!("version history is not supported by '{}'", self.kind().kind),
+        })
+    }
+    /// Wait until the values of a mapping may have changed, for at most `timeout`, and tell
+    /// whether the backend reported a change. Even then a value need not have changed.
+    /// Providers without change notifications wait out the timeout, so callers end up polling.
+    ///
+    /// # Errors
+    ///
+    /// ...
+    async fn watch(&self, _pm: &PathMap, timeout: Duration) -> Result<bool> {
+        tokio::time::sleep(timeout).await;
+        Ok(false)
+    }
 }
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -68,6 +106,134 @@ pub enum Error {
 
     #[error("{0}")]
     CreateProviderError(String),
+
+    #[error("TIMEOUT {path}: {msg}")]
+    Timeout { path: String, msg: String },
+}
+
+/// Signs of a transient failure in a backend error message. Providers report most backend
+/// failures as text, so this is the best classification available for them. Signs match whole
+/// words, a trailing `*` matches any word starting with the sign.
+const TRANSIENT_SIGNS: &[&str] = &[
+    "timed out",
+    "timeout",
+    "connection refused",
+    "connection reset",
+    "connection closed",
+    "broken pipe",
+    "dispatch failure",
+    "temporarily unavailable",
+    "service unavailable",
+    "too many requests",
+    "throttl*",
+    "rate exceeded",
+    "429",
+    "502",
+    "503",
+    "504",
+];
+
+/// Signs of rejected credentials or missing permissions in a backend error message
+const AUTH_SIGNS: &[&str] = &[
+    "permission denied",
+    "access denied",
+    "accessdenied*",
+    "unauthorized",
+    "unauthenticated",
+    "forbidden",
+    "invalid token",
+    "expired token",
+    "expiredtoken*",
+    "credentials",
+    "401",
+    "403",
+];
+
+impl Error {
+    /// Whether retrying the same call may succeed: timeouts, connection failures, throttling
+    /// and unavailable backends. Everything else (not found, bad configuration, denied access,
+    /// malformed data) is permanent.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout { .. } => true,
+            Self::IO(err) => is_transient_io(err),
+            Self::GetError { msg, .. }
+            | Self::PutError { msg, .. }
+            | Self::DeleteError { msg, .. }
+            | Self::ListError { msg, .. } => is_transient_message(msg),
+            Self::Any(err) => err
+                .downcast_ref::<std::io::Error>()
+                .map_or_else(|| is_transient_message(&err.to_string()), is_transient_io),
+            Self::Message(_)
+            | Self::PathError(..)
+            | Self::Env(_)
+            | Self::Json(_)
+            | Self::YAML(_)
+            | Self::NotFound { .. }
+            | Self::CreateProviderError(_) => false,
+        }
+    }
+
+    /// Whether the backend refused the caller: bad or missing credentials, or not enough
+    /// permissions for the path
+    #[must_use]
+    pub fn is_auth_error(&self) -> bool {
+        match self {
+            Self::IO(err) => err.kind() == std::io::ErrorKind::PermissionDenied,
+            Self::NotFound { .. } | Self::Timeout { .. } => false,
+            err => {
+                let msg = err.to_string().to_lowercase();
+                AUTH_SIGNS.iter().any(|sign| mentions(&msg, sign))
+            }
+        }
+    }
+
+    /// Whether the call gave up waiting for the backend, which may still have carried it out
+    #[must_use]
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Self::Timeout { .. } => true,
+            Self::IO(err) => err.kind() == std::io::ErrorKind::TimedOut,
+            Self::NotFound { .. } => false,
+            err => {
+                let msg = err.to_string().to_lowercase();
+                mentions(&msg, "timed out") || mentions(&msg, "timeout")
+            }
+        }
+    }
+}
+
+fn is_transient_io(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        err.kind(),
+        ErrorKind::TimedOut
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::Interrupted
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+fn is_transient_message(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    TRANSIENT_SIGNS.iter().any(|sign| mentions(&msg, sign))
+}
+
+/// Whether `msg` holds `sign` as whole words, so a `503` in an id or a port doesn't count
+fn mentions(msg: &str, sign: &str) -> bool {
+    let (sign, prefix) = sign
+        .strip_suffix('*')
+        .map_or((sign, false), |sign| (sign, true));
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    msg.match_indices(sign).any(|(at, _)| {
+        !is_word(msg[..at].chars().next_back())
+            && (prefix || !is_word(msg[at + sign.len()..].chars().next()))
+    })
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
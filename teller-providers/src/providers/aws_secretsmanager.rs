@@ -29,7 +29,7 @@ use serde_derive::{Deserialize, Serialize};
 use super::ProviderKind;
 use crate::config::ProviderInfo;
 use crate::{
-    config::{PathMap, KV},
+    config::{PathMap, VersionInfo, KV},
     Error, Provider, Result,
 };
 
@@ -155,9 +155,16 @@ async fn get_data(
     client: &secretsmanager::Client,
     pm: &PathMap,
 ) -> Result<Option<BTreeMap<String, String>>> {
+    // only plain reads honor a pinned version, writes merge into the current one
+    let version = if mode == &Mode::Get {
+        pm.version.clone()
+    } else {
+        None
+    };
     let resp = client
         .get_secret_value()
         .secret_id(&pm.path)
+        .set_version_id(version)
         .send()
         .await
         .map_or_else(
@@ -257,6 +264,40 @@ impl Provider for AWSSecretsManager {
         }
         Ok(())
     }
+
+    async fn history(&self, pm: &PathMap) -> Result<Vec<VersionInfo>> {
+        let resp = self
+            .client
+            .list_secret_version_ids()
+            .secret_id(&pm.path)
+            .include_deprecated(true)
+            .into_paginator()
+            .send()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .await
+            .map_err(|e| Error::GetError {
+                msg: e.to_string(),
+                path: pm.path.clone(),
+            })?;
+
+        let mut versions = resp
+            .iter()
+            .flat_map(|page| page.versions())
+            .filter_map(|entry| {
+                let stages = entry.version_stages();
+                Some(VersionInfo {
+                    key: None,
+                    version: entry.version_id()?.to_string(),
+                    created_at: entry.created_date().map(ToString::to_string),
+                    current: stages.iter().any(|stage| stage == "AWSCURRENT"),
+                    // versions without a staging label are deprecated
+                    deleted: stages.is_empty(),
+                })
+            })
+            .collect::<Vec<_>>();
+        versions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(versions)
+    }
 }
 
 #[cfg(test)]
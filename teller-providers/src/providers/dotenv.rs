@@ -18,7 +18,7 @@
 use std::fs::File;
 use std::io::prelude::*;
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     io,
     path::Path,
 };
@@ -31,7 +31,7 @@ use serde_derive::{Deserialize, Serialize};
 use super::ProviderKind;
 use crate::config::ProviderInfo;
 use crate::{
-    config::{PathMap, KV},
+    config::{ListEntry, PathMap, KV},
     Error, Provider, Result,
 };
 
@@ -69,7 +69,16 @@ impl Dotenv {
 }
 
 fn load(path: &Path, mode: &Mode) -> Result<BTreeMap<String, String>> {
-    let content = fs::File::open(path)?;
+    let content = fs::File::open(path).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            Error::NotFound {
+                path: format!("{path:?}"),
+                msg: "file not found".to_string(),
+            }
+        } else {
+            Error::IO(e)
+        }
+    })?;
     let mut env = BTreeMap::new();
 
     if mode == &Mode::Get {
@@ -167,6 +176,38 @@ impl Provider for Dotenv {
         )?;
         Ok(())
     }
+
+    /// Lists the keys of a dotenv file, or the dotenv files (`*.env`, `.env*`) and
+    /// folders in a directory
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        let path = Path::new(if prefix.is_empty() { "." } else { prefix });
+        if path.is_file() {
+            return Ok(load(path, &Mode::Get)?
+                .into_keys()
+                .map(ListEntry::Key)
+                .collect());
+        }
+
+        let mut entries = BTreeSet::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let entry_path = entry.path();
+            let is_listed = if entry_path.is_dir() {
+                !name.starts_with('.')
+            } else {
+                name.starts_with(".env") || name.ends_with(".env")
+            };
+            if is_listed {
+                entries.insert(ListEntry::Path(if prefix.is_empty() {
+                    name
+                } else {
+                    entry_path.to_string_lossy().to_string()
+                }));
+            }
+        }
+        Ok(entries.into_iter().collect())
+    }
 }
 impl Dotenv {
     fn load_modify_save<F>(&self, pm: &PathMap, modify: F, mode: &Mode) -> Result<()>
@@ -14,14 +14,16 @@
 //! See [`EtcdOptions`] for more.
 //!
 
+use std::{collections::BTreeSet, time::Duration};
+
 use async_trait::async_trait;
-use etcd_client::{Client, ConnectOptions, DeleteOptions, GetOptions};
+use etcd_client::{Client, ConnectOptions, DeleteOptions, GetOptions, WatchOptions};
 use serde_derive::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-use super::ProviderKind;
+use super::{list_entry, ProviderKind};
 use crate::{
-    config::{PathMap, ProviderInfo, KV},
+    config::{ListEntry, PathMap, ProviderInfo, KV},
     Error, Provider, Result,
 };
 
@@ -179,7 +181,7 @@ impl Provider for Etcd {
                 .await
                 .map_err(|err| to_err(pm, err))?;
         } else {
-            for key in pm.keys.keys().map(|kv| format!("{}/{kv}", &pm.path)) {
+            for key in pm.keys.keys().map(|kv| format!("{}/{kv}", pm.path)) {
                 client
                     .delete(key, None)
                     .await
@@ -190,6 +192,46 @@ impl Provider for Etcd {
 
         Ok(())
     }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        let pm = PathMap::from_path(prefix);
+        let mut client = self.client.lock().await.kv_client();
+        let res = client
+            .get(
+                prefix,
+                Some(GetOptions::new().with_prefix().with_keys_only()),
+            )
+            .await
+            .map_err(|err| to_err(&pm, err))?;
+        drop(client);
+
+        let mut entries = BTreeSet::new();
+        for kv_pair in res.kvs() {
+            let key = kv_pair.key_str().map_err(|err| to_err(&pm, err))?;
+            if let Some(entry) = list_entry(prefix, key) {
+                entries.insert(entry);
+            }
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    async fn watch(&self, pm: &PathMap, timeout: Duration) -> Result<bool> {
+        let mut client = self.client.lock().await.watch_client();
+        // the whole prefix: `get` reads either all of it or keys under it
+        let (mut watcher, mut stream) = client
+            .watch(pm.path.as_str(), Some(WatchOptions::new().with_prefix()))
+            .await
+            .map_err(|err| to_err(pm, err))?;
+        drop(client);
+        let res = tokio::time::timeout(timeout, stream.message()).await;
+        watcher.cancel().await.map_err(|err| to_err(pm, err))?;
+        match res {
+            Ok(message) => message
+                .map(|resp| resp.is_some_and(|resp| !resp.events().is_empty()))
+                .map_err(|err| to_err(pm, err)),
+            Err(_elapsed) => Ok(false),
+        }
+    }
 }
 
 #[cfg(test)]
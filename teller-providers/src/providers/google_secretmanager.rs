@@ -33,7 +33,7 @@ use google_secretmanager1::{
 
 use super::ProviderKind;
 use crate::{
-    config::{PathMap, ProviderInfo, KV},
+    config::{ListEntry, PathMap, ProviderInfo, VersionInfo, KV},
     Error, Provider, Result,
 };
 
@@ -41,6 +41,8 @@ use crate::{
 pub trait GSM {
     fn get_hub(&self) -> Option<&SecretManager<HttpsConnector<HttpConnector>>>;
     async fn list(&self, name: &str) -> Result<Vec<(String, String)>>;
+    async fn list_names(&self, name: &str) -> Result<Vec<String>>;
+    async fn versions(&self, name: &str) -> Result<Vec<VersionInfo>>;
     async fn get(&self, name: &str) -> Result<Option<String>>;
     async fn put(&self, name: &str, value: &str) -> Result<()>;
     async fn del(&self, name: &str) -> Result<()>;
@@ -108,6 +110,64 @@ impl GSM for GSMClient {
         Ok(out)
     }
 
+    async fn list_names(&self, name: &str) -> Result<Vec<String>> {
+        let hub = self.get_hub().expect("hub");
+
+        let (_, secret) = hub
+            .projects()
+            .secrets_list(name)
+            .doit()
+            .await
+            .map_err(|e| Error::ListError {
+                path: name.to_string(),
+                msg: e.to_string(),
+            })?;
+
+        Ok(secret
+            .secrets
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|secret| secret.name)
+            .collect())
+    }
+
+    async fn versions(&self, name: &str) -> Result<Vec<VersionInfo>> {
+        let hub = self.get_hub().expect("hub");
+
+        let mut out = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut call = hub.projects().secrets_versions_list(name);
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            let (_, resp) = call.doit().await.map_err(|e| Error::GetError {
+                path: name.to_string(),
+                msg: e.to_string(),
+            })?;
+            // projects/123/secrets/FOOBAR/versions/3 -> 3
+            out.extend(
+                resp.versions
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|v| {
+                        Some(VersionInfo {
+                            key: None,
+                            version: v.name?.rsplit_once('/')?.1.to_string(),
+                            created_at: v.create_time.map(|t| t.to_rfc3339()),
+                            current: false,
+                            deleted: v.state.as_deref() != Some("ENABLED"),
+                        })
+                    }),
+            );
+            page_token = resp.next_page_token.filter(|token| !token.is_empty());
+            if page_token.is_none() {
+                break;
+            }
+        }
+        Ok(newest_first(out))
+    }
+
     async fn get(&self, name: &str) -> Result<Option<String>> {
         let hub = self.get_hub().expect("hub");
         let resource = if name.contains("/versions") {
@@ -250,6 +310,22 @@ async fn resolve_auth() -> Result<Authenticator<oauth2::hyper_rustls::HttpsConne
         .map_err(Box::from)?)
 }
 
+/// Sorts by version number, newest first, marking the newest as the one `latest` resolves to
+fn newest_first(mut versions: Vec<VersionInfo>) -> Vec<VersionInfo> {
+    versions.sort_by_key(|v| std::cmp::Reverse(v.version.parse::<u64>().unwrap_or_default()));
+    if let Some(latest) = versions.first_mut() {
+        latest.current = true;
+    }
+    versions
+}
+
+fn versioned(resource: &str, version: Option<&str>) -> String {
+    version.map_or_else(
+        || resource.to_string(),
+        |version| format!("{resource}/versions/{version}"),
+    )
+}
+
 pub struct GoogleSecretManager {
     client: Box<dyn GSM + Send + Sync>,
     pub name: String,
@@ -276,7 +352,18 @@ impl Provider for GoogleSecretManager {
 
     async fn get(&self, pm: &PathMap) -> Result<Vec<KV>> {
         let mut out = Vec::new();
-        if pm.keys.is_empty() {
+        if let (true, Some(version)) = (pm.keys.is_empty(), &pm.version) {
+            // a pinned version of every secret under the path
+            for resource in self.client.list_names(&pm.path).await? {
+                let val = self
+                    .client
+                    .get(&versioned(&resource, Some(version)))
+                    .await?;
+                if let (Some(val), Some((_, key))) = (val, resource.rsplit_once('/')) {
+                    out.push(KV::from_value(&val, key, key, pm, self.kind()));
+                }
+            }
+        } else if pm.keys.is_empty() {
             // get parameters by path
             // ("projects/1xxx34/secrets/DSN4", "foobar")
             let values = self.client.list(&pm.path).await?;
@@ -292,7 +379,10 @@ impl Provider for GoogleSecretManager {
             for (k, v) in &pm.keys {
                 let resp = self
                     .client
-                    .get(&format!("{}/secrets/{}", pm.path, k))
+                    .get(&versioned(
+                        &format!("{}/secrets/{}", pm.path, k),
+                        pm.version.as_deref(),
+                    ))
                     .await?;
                 if let Some(val) = resp {
                     out.push(KV::from_value(&val, k, v, pm, self.kind()));
@@ -334,6 +424,45 @@ impl Provider for GoogleSecretManager {
         }
         Ok(())
     }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        let mut names = self.client.list_names(prefix).await?;
+        names.sort();
+        // projects/123/secrets/FOOBAR -> FOOBAR
+        Ok(names
+            .iter()
+            .filter_map(|resource| resource.rsplit_once('/'))
+            .map(|(_, key)| ListEntry::Key(key.to_string()))
+            .collect())
+    }
+
+    async fn history(&self, pm: &PathMap) -> Result<Vec<VersionInfo>> {
+        // every secret is versioned on its own
+        let keys = if pm.keys.is_empty() {
+            self.client
+                .list_names(&pm.path)
+                .await?
+                .iter()
+                .filter_map(|resource| resource.rsplit_once('/'))
+                .map(|(_, key)| key.to_string())
+                .collect::<Vec<_>>()
+        } else {
+            pm.keys.keys().cloned().collect::<Vec<_>>()
+        };
+
+        let mut out = Vec::new();
+        for key in keys {
+            let versions = self
+                .client
+                .versions(&format!("{}/secrets/{}", pm.path, key))
+                .await?;
+            out.extend(versions.into_iter().map(|v| VersionInfo {
+                key: Some(key.clone()),
+                ..v
+            }));
+        }
+        Ok(out)
+    }
 }
 
 #[cfg(test)]
@@ -349,12 +478,14 @@ mod tests {
     use google_secretmanager1::SecretManager;
 
     use crate::{
+        config::{PathMap, VersionInfo},
         providers::{google_secretmanager::GSM, test_utils},
         Provider, Result,
     };
 
+    // every secret holds all of its versions, oldest first
     struct MockClient {
-        data: Arc<Mutex<BTreeMap<String, String>>>,
+        data: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
     }
 
     impl MockClient {
@@ -382,19 +513,52 @@ mod tests {
                 .unwrap()
                 .iter()
                 .filter(|(k, _)| k.starts_with(name))
-                .map(|(k, v)| (k.clone(), v.clone()))
+                .filter_map(|(k, v)| Some((k.clone(), v.last()?.clone())))
+                .collect::<Vec<_>>())
+        }
+
+        async fn list_names(&self, name: &str) -> Result<Vec<String>> {
+            Ok(self
+                .data
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|k| k.starts_with(name))
+                .cloned()
                 .collect::<Vec<_>>())
         }
 
+        async fn versions(&self, name: &str) -> Result<Vec<VersionInfo>> {
+            let count = self.data.lock().unwrap().get(name).map_or(0, Vec::len);
+            Ok(super::newest_first(
+                (1..=count)
+                    .map(|n| VersionInfo {
+                        version: n.to_string(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ))
+        }
+
         async fn get(&self, name: &str) -> Result<Option<String>> {
-            Ok(self.data.lock().unwrap().get(name).cloned())
+            let data = self.data.lock().unwrap();
+            if let Some((name, version)) = name.split_once("/versions/") {
+                let index = version.parse::<usize>().unwrap_or_default();
+                return Ok(data
+                    .get(name)
+                    .and_then(|versions| versions.get(index.wrapping_sub(1)))
+                    .cloned());
+            }
+            Ok(data.get(name).and_then(|versions| versions.last()).cloned())
         }
 
         async fn put(&self, name: &str, value: &str) -> Result<()> {
             self.data
                 .lock()
                 .unwrap()
-                .insert(name.to_string(), value.to_string());
+                .entry(name.to_string())
+                .or_default()
+                .push(value.to_string());
             Ok(())
         }
 
@@ -414,4 +578,29 @@ mod tests {
 
         test_utils::ProviderTest::new(p).run().await;
     }
+
+    #[tokio::test]
+    async fn versions_test() {
+        let p = super::GoogleSecretManager::new("test", Box::new(MockClient::new()));
+        let mut pm = PathMap::from_path("projects/1");
+        pm.keys.insert("TOKEN".to_string(), "TOKEN".to_string());
+        for value in ["first", "second"] {
+            p.put(&pm, &[crate::config::KV::from_kv("TOKEN", value)])
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(p.get(&pm).await.unwrap()[0].value, "second");
+        pm.version = Some("1".to_string());
+        assert_eq!(p.get(&pm).await.unwrap()[0].value, "first");
+
+        let history = p.history(&pm).await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|v| (v.key.as_deref(), v.version.as_str(), v.current))
+                .collect::<Vec<_>>(),
+            vec![(Some("TOKEN"), "2", true), (Some("TOKEN"), "1", false)]
+        );
+    }
 }
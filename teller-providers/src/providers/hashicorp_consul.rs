@@ -14,15 +14,15 @@
 //! See [`HashiCorpConsulOptions`] for more.
 //!
 #![allow(clippy::borrowed_box)]
-use std::env;
+use std::{collections::BTreeSet, env, time::Duration};
 
 use async_trait::async_trait;
 use rs_consul::{Consul, ConsulError};
 use serde_derive::{Deserialize, Serialize};
 
-use super::ProviderKind;
+use super::{list_entry, ProviderKind};
 use crate::{
-    config::{PathMap, ProviderInfo, KV},
+    config::{ListEntry, PathMap, ProviderInfo, KV},
     Error, Provider, Result,
 };
 
@@ -176,7 +176,7 @@ impl Provider for HashiCorpConsul {
         } else {
             pm.keys
                 .keys()
-                .map(|kv| format!("{}/{kv}", &pm.path))
+                .map(|kv| format!("{}/{kv}", pm.path))
                 .collect::<Vec<_>>()
         };
 
@@ -193,6 +193,58 @@ impl Provider for HashiCorpConsul {
 
         Ok(())
     }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        let pm = PathMap::from_path(prefix);
+        let res = self
+            .consul
+            .read_key(rs_consul::ReadKeyRequest {
+                key: prefix,
+                datacenter: &self.opts.dc.clone().unwrap_or_default(),
+                recurse: true,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| to_err(&pm, e))?;
+
+        Ok(res
+            .iter()
+            .filter_map(|resp| list_entry(prefix, &resp.key))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect())
+    }
+
+    async fn watch(&self, pm: &PathMap, timeout: Duration) -> Result<bool> {
+        let datacenter = self.opts.dc.clone().unwrap_or_default();
+        let request = rs_consul::ReadKeyRequest {
+            key: &pm.path,
+            datacenter: &datacenter,
+            recurse: false,
+            ..Default::default()
+        };
+        let index = self
+            .consul
+            .read_key(request.clone())
+            .await
+            .map_err(|e| to_err(pm, e))?
+            .iter()
+            .map(|resp| resp.modify_index)
+            .max()
+            .unwrap_or_default();
+        // a blocking query: consul answers once the key is modified past `index`, or after `wait`
+        let blocking = self.consul.read_key(rs_consul::ReadKeyRequest {
+            index: Some(u64::try_from(index).unwrap_or_default()),
+            wait: timeout,
+            ..request
+        });
+        match tokio::time::timeout(timeout + Duration::from_secs(5), blocking).await {
+            Ok(Err(e)) => Err(to_err(pm, e)),
+            // consul also answers when `wait` passes, with the index unchanged
+            Ok(Ok(resps)) => Ok(resps.iter().map(|resp| resp.modify_index).max() != Some(index)),
+            Err(_) => Ok(false),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -29,7 +29,7 @@ use vaultrs::{
 
 use super::ProviderKind;
 use crate::{
-    config::{PathMap, ProviderInfo, KV},
+    config::{ListEntry, PathMap, ProviderInfo, VersionInfo, KV},
     Error, Provider, Result,
 };
 
@@ -130,20 +130,35 @@ fn xerr(pm: &PathMap, e: ClientError) -> Error {
     }
 }
 
-async fn get_data(client: &VaultClient, pm: &PathMap) -> Result<BTreeMap<String, String>> {
+async fn get_data(
+    client: &VaultClient,
+    pm: &PathMap,
+    version: Option<&str>,
+) -> Result<BTreeMap<String, String>> {
     let (engine, mount, path) = parse_path(pm)?;
-    let data = if engine == "kv2" {
-        kv2::read(client, mount, path).await
-    } else {
-        kv1::get(client, mount, path).await
+    let data = match (engine, version) {
+        ("kv2", None) => kv2::read(client, mount, path).await,
+        ("kv2", Some(version)) => {
+            let version = version.parse::<u64>().map_err(|_| {
+                Error::Message(format!("kv2 version must be a number, got '{version}'"))
+            })?;
+            kv2::read_version(client, mount, path, version).await
+        }
+        (_, None) => kv1::get(client, mount, path).await,
+        (engine, Some(_)) => {
+            return Err(Error::Message(format!(
+                "engine '{engine}' does not keep versions, use kv2"
+            )))
+        }
     }
     .map_err(|e| xerr(pm, e))?;
 
     Ok(data)
 }
 
+// always the latest version: writes merge into what is current, regardless of a pinned version
 async fn get_data_or_empty(client: &VaultClient, pm: &PathMap) -> Result<BTreeMap<String, String>> {
-    let data = match get_data(client, pm).await {
+    let data = match get_data(client, pm, None).await {
         Ok(data) => data,
         Err(Error::NotFound { path: _, msg: _ }) => BTreeMap::new(),
         Err(e) => return Err(e),
@@ -188,13 +203,15 @@ impl Provider for Hashivault {
 
     async fn get(&self, pm: &PathMap) -> Result<Vec<KV>> {
         Ok(KV::from_data(
-            &get_data(&self.client, pm).await.map_err(|e| match e {
-                Error::NotFound { path, msg } => Error::NotFound { path, msg },
-                _ => Error::GetError {
-                    path: pm.path.to_string(),
-                    msg: e.to_string(),
-                },
-            })?,
+            &get_data(&self.client, pm, pm.version.as_deref())
+                .await
+                .map_err(|e| match e {
+                    Error::NotFound { path, msg } => Error::NotFound { path, msg },
+                    _ => Error::GetError {
+                        path: pm.path.to_string(),
+                        msg: e.to_string(),
+                    },
+                })?,
             pm,
             &self.kind(),
         ))
@@ -264,6 +281,57 @@ impl Provider for Hashivault {
         };
         Ok(())
     }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        let pm = PathMap::from_path(prefix.trim_end_matches('/'));
+        let (mount, path) = pm.path.split_once('/').unwrap_or((pm.path.as_str(), ""));
+        let names = match kv2::list(&self.client, mount, path).await {
+            Ok(names) => names,
+            Err(kv2_err) => match kv1::list(&self.client, mount, path).await {
+                Ok(res) => res.data.keys,
+                Err(_) => {
+                    // not a folder, the prefix may be a secret holding keys
+                    return get_data(&self.client, &pm, None)
+                        .await
+                        .map(|data| data.into_keys().map(ListEntry::Key).collect())
+                        .map_err(|_| xerr(&pm, kv2_err));
+                }
+            },
+        };
+        Ok(names
+            .iter()
+            .map(|name| ListEntry::Path(format!("{}/{}", pm.path, name.trim_end_matches('/'))))
+            .collect())
+    }
+    async fn history(&self, pm: &PathMap) -> Result<Vec<VersionInfo>> {
+        let (engine, mount, path) = parse_path(pm)?;
+        if engine != "kv2" {
+            return Err(Error::GetError {
+                path: pm.path.clone(),
+                msg: format!("engine '{engine}' does not keep versions, use kv2"),
+            });
+        }
+        let metadata = kv2::read_metadata(&self.client, mount, path)
+            .await
+            .map_err(|e| xerr(pm, e))?;
+
+        let mut versions = metadata
+            .versions
+            .into_iter()
+            .filter_map(|(version, meta)| Some((version.parse::<u64>().ok()?, meta)))
+            .collect::<Vec<_>>();
+        versions.sort_by_key(|(version, _)| std::cmp::Reverse(*version));
+        Ok(versions
+            .into_iter()
+            .map(|(version, meta)| VersionInfo {
+                key: None,
+                version: version.to_string(),
+                created_at: Some(meta.created_time),
+                current: version == metadata.current_version,
+                deleted: meta.destroyed || !meta.deletion_time.is_empty(),
+            })
+            .collect())
+    }
 }
 
 #[cfg(test)]
@@ -17,13 +17,16 @@
 //! representation and can be any `serde_json::Value` that can convert to
 //! a `BTreeMap` (hashmap)
 //!
-use std::{collections::BTreeMap, sync::Mutex};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+};
 
 use async_trait::async_trait;
 
-use super::ProviderKind;
+use super::{list_entry, ProviderKind};
 use crate::{
-    config::{PathMap, ProviderInfo, KV},
+    config::{ListEntry, PathMap, ProviderInfo, KV},
     Error, Provider, Result,
 };
 
@@ -128,6 +131,28 @@ impl Provider for Inmem {
 
         Ok(())
     }
+
+    #[allow(clippy::significant_drop_tightening)]
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        let store = self.store.lock().unwrap();
+        let mut entries = BTreeSet::new();
+        if let Some(data) = store.get(prefix) {
+            entries.extend(data.keys().cloned().map(ListEntry::Key));
+        }
+        // every stored path is a location, even when it sits directly under the prefix
+        for path in store.keys() {
+            match list_entry(prefix, path) {
+                Some(ListEntry::Key(_)) => {
+                    entries.insert(ListEntry::Path(path.clone()));
+                }
+                Some(entry) => {
+                    entries.insert(entry);
+                }
+                None => {}
+            }
+        }
+        Ok(entries.into_iter().collect())
+    }
 }
 
 #[cfg(test)]
@@ -144,4 +169,30 @@ mod tests {
 
         test_utils::ProviderTest::new(p).run().await;
     }
+
+    #[test]
+    async fn list_test() {
+        use crate::config::ListEntry;
+
+        let p = super::Inmem::from_yaml(
+            "test",
+            r"
+app/dev: { A: '1' }
+app/prod/db: { B: '2' }
+other: { C: '3' }
+",
+        )
+        .unwrap();
+        assert_eq!(
+            p.list("app").await.unwrap(),
+            vec![
+                ListEntry::Path("app/dev".to_string()),
+                ListEntry::Path("app/prod".to_string()),
+            ]
+        );
+        assert_eq!(
+            p.list("app/dev").await.unwrap(),
+            vec![ListEntry::Key("A".to_string())]
+        );
+    }
 }
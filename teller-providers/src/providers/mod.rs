@@ -5,6 +5,8 @@ use serde::{Deserialize, Serialize};
 use serde_variant::to_variant_name;
 use strum::{EnumIter, IntoEnumIterator};
 
+use crate::config::ListEntry;
+
 #[cfg(test)]
 mod test_utils;
 
@@ -30,6 +32,9 @@ pub mod hashicorp_consul;
 #[cfg(feature = "etcd")]
 pub mod etcd;
 
+#[cfg(feature = "plugin")]
+pub mod plugin;
+
 lazy_static! {
     pub static ref PROVIDER_KINDS: String = {
         let providers: Vec<String> = ProviderKind::iter()
@@ -73,11 +78,62 @@ pub enum ProviderKind {
     #[cfg(feature = "etcd")]
     #[serde(rename = "etcd")]
     Etcd,
+
+    #[cfg(feature = "plugin")]
+    #[serde(rename = "plugin")]
+    Plugin,
+
+    /// A kind teller does not know, provided by a factory registered with
+    /// [`crate::registry::RegistryBuilder::with_factory`]
+    #[serde(untagged)]
+    #[strum(disabled)]
+    Custom(String),
+}
+
+/// Classifies a full `name` found while listing `prefix`: a name directly under
+/// `prefix` is a [`ListEntry::Key`], anything deeper is represented by the
+/// [`ListEntry::Path`] of its direct child of `prefix`.
+pub(crate) fn list_entry(prefix: &str, name: &str) -> Option<ListEntry> {
+    let base = prefix.trim_end_matches('/');
+    let rest = name.strip_prefix(base)?;
+    if !base.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    let rest = rest.trim_start_matches('/');
+    if rest.is_empty() {
+        return None;
+    }
+    Some(match rest.split_once('/') {
+        Some((child, _)) => {
+            ListEntry::Path(name[..name.len() - rest.len() + child.len()].to_string())
+        }
+        None => ListEntry::Key(rest.to_string()),
+    })
+}
+
+impl ProviderKind {
+    /// Whether this provider keeps secret versions, and so honors a map's `version`
+    #[must_use]
+    pub const fn is_versioned(&self) -> bool {
+        match self {
+            #[cfg(feature = "hashicorp_vault")]
+            Self::Hashicorp => true,
+            #[cfg(feature = "aws_secretsmanager")]
+            Self::AWSSecretsManager => true,
+            #[cfg(feature = "google_secretmanager")]
+            Self::GoogleSecretManager => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for ProviderKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        to_variant_name(self).expect("only enum supported").fmt(f)
+        match self {
+            Self::Custom(kind) => kind.fmt(f),
+            _ => to_variant_name(self).expect("only enum supported").fmt(f),
+        }
     }
 }
 
@@ -95,3 +151,38 @@ impl FromStr for ProviderKind {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_entries() {
+        assert_eq!(
+            list_entry("/app", "/app/db/password"),
+            Some(ListEntry::Path("/app/db".to_string()))
+        );
+        assert_eq!(
+            list_entry("/app/", "/app/token"),
+            Some(ListEntry::Key("token".to_string()))
+        );
+        assert_eq!(
+            list_entry("", "app/token"),
+            Some(ListEntry::Path("app".to_string()))
+        );
+        assert_eq!(list_entry("/app", "/application/token"), None);
+        assert_eq!(list_entry("app", "app/"), None);
+    }
+
+    #[test]
+    fn custom_kinds() {
+        let kind: ProviderKind = serde_yaml::from_str("acme_vault").unwrap();
+        assert_eq!(kind, ProviderKind::Custom("acme_vault".to_string()));
+        assert_eq!(kind.to_string(), "acme_vault");
+        assert_eq!(serde_yaml::to_string(&kind).unwrap(), "acme_vault\n");
+
+        let kind: ProviderKind = serde_yaml::from_str("inmem").unwrap();
+        assert_eq!(kind, ProviderKind::Inmem);
+        assert!(ProviderKind::iter().all(|kind| !matches!(kind, ProviderKind::Custom(_))));
+    }
+}
@@ -0,0 +1,611 @@
+//! Out-of-process Plugins
+//!
+//!
+//! ## Example configuration
+//!
+//! ```yaml
+//! providers:
+//!  acme1:
+//!    kind: plugin
+//!    options:
+//!      command: teller-plugin-acme
+//!      args: ["--region", "eu"]
+//!      # any other option is handed to the plugin as-is
+//! ```
+//! ## Options
+//!
+//! See [`PluginOptions`]
+//!
+//! ## Protocol
+//!
+//! Teller spawns `command` once and speaks JSON-RPC 2.0 over the plugin's stdin and stdout,
+//! one JSON message per line. The plugin's stderr is inherited, use it for logs.
+//!
+//! The first call is always `kind`, which negotiates the protocol version:
+//!
+//! ```text
+//! -> {"jsonrpc":"2.0","id":1,"method":"kind","params":{"protocol_version":1,"name":"acme1","options":{..}}}
+//! <- {"jsonrpc":"2.0","id":1,"result":{"protocol_version":1,"kind":"acme"}}
+//! ```
+//!
+//! Then, per operation (`path_map` is the map from the configuration, as YAML fields):
+//!
+//! * `get`, params `{"path_map": ..}`, result `{"data": {"KEY": "value", ..}}` with the keys as
+//!   stored at the path. Teller applies the map's `keys` itself.
+//! * `put`, params `{"path_map": .., "data": {"KEY": "value", ..}}`, result `{}`
+//! * `del`, params `{"path_map": ..}`, result `{}`. When the map has `keys`, only delete those.
+//!
+//! Failures are JSON-RPC errors. Use code [`NOT_FOUND`] for a missing path, anything else is
+//! reported as a provider error with its message.
+//!
+//! Plugins written in Rust can expose any [`Provider`] with [`serve`], see
+//! `examples/inmem_plugin.rs` for a reference plugin.
+#![allow(clippy::borrowed_box)]
+use std::{collections::BTreeMap, process::Stdio};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    sync::Mutex,
+};
+
+use super::ProviderKind;
+use crate::{
+    config::{PathMap, ProviderInfo, KV},
+    Error, Provider, Result,
+};
+
+/// The protocol version spoken by this build of teller
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Error code for a path that does not exist
+pub const NOT_FOUND: i64 = -32001;
+/// Error code for any other provider failure
+pub const PROVIDER_ERROR: i64 = -32000;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+///
+/// # Plugin provider configuration
+///
+/// Options other than `command` and `args` are sent to the plugin in the `kind` call.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PluginOptions {
+    /// The plugin executable, looked up in `PATH`
+    pub command: String,
+    /// Arguments for the plugin executable
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Request {
+    jsonrpc: String,
+    id: u64,
+    method: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Response {
+    jsonrpc: String,
+    id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct KindParams {
+    protocol_version: u32,
+    name: String,
+    #[serde(default)]
+    options: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct KindResult {
+    protocol_version: u32,
+    kind: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MapParams {
+    path_map: PathMap,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DataResult {
+    #[serde(default)]
+    data: BTreeMap<String, String>,
+}
+
+struct Conn {
+    reader: Box<dyn AsyncBufRead + Unpin + Send>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+    next_id: u64,
+    /// The response being read. It outlives calls, so a call dropped halfway through a
+    /// line leaves the rest of it to be read and discarded by the next one.
+    buf: Vec<u8>,
+}
+
+pub struct Plugin {
+    pub name: String,
+    /// The kind reported by the plugin itself
+    pub plugin_kind: String,
+    conn: Mutex<Conn>,
+    // keeps the process alive for as long as the provider, killed on drop
+    _child: Option<Child>,
+}
+
+impl Plugin {
+    /// Spawn the plugin executable and connect to it
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the plugin cannot be spawned, or does not speak
+    /// this protocol version
+    pub async fn new(name: &str, opts: Option<serde_json::Value>) -> Result<Self> {
+        let opts = opts.ok_or_else(|| {
+            Error::CreateProviderError(format!("plugin '{name}' requires a `command` option"))
+        })?;
+        let plugin_opts: PluginOptions = serde_json::from_value(opts.clone())?;
+
+        let mut child = Command::new(&plugin_opts.command)
+            .args(&plugin_opts.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                Error::CreateProviderError(format!(
+                    "cannot start plugin '{name}' ({}): {e}",
+                    plugin_opts.command
+                ))
+            })?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(Error::CreateProviderError(format!(
+                "cannot connect to plugin '{name}'"
+            )));
+        };
+
+        let mut plugin = Self::connect(name, BufReader::new(stdout), stdin, Some(opts)).await?;
+        plugin._child = Some(child);
+        Ok(plugin)
+    }
+
+    /// Connect to a plugin over an existing transport and negotiate the protocol version
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the plugin does not speak this protocol version
+    pub async fn connect<R, W>(
+        name: &str,
+        reader: R,
+        writer: W,
+        options: Option<serde_json::Value>,
+    ) -> Result<Self>
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut plugin = Self {
+            name: name.to_string(),
+            plugin_kind: String::new(),
+            conn: Mutex::new(Conn {
+                reader: Box::new(reader),
+                writer: Box::new(writer),
+                next_id: 1,
+                buf: Vec::new(),
+            }),
+            _child: None,
+        };
+
+        let res: KindResult = plugin
+            .call(
+                "kind",
+                KindParams {
+                    protocol_version: PROTOCOL_VERSION,
+                    name: name.to_string(),
+                    options,
+                },
+            )
+            .await
+            .map_err(|e| {
+                Error::CreateProviderError(format!("plugin '{name}' handshake failed: {e}"))
+            })?;
+        if res.protocol_version != PROTOCOL_VERSION {
+            return Err(Error::CreateProviderError(format!(
+                "plugin '{name}' speaks protocol version {}, teller requires {PROTOCOL_VERSION}",
+                res.protocol_version
+            )));
+        }
+        plugin.plugin_kind = res.kind;
+        Ok(plugin)
+    }
+
+    async fn call<P: serde::Serialize + Send, T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> std::result::Result<T, RpcError> {
+        let io_err = |e: std::io::Error| RpcError {
+            code: PROVIDER_ERROR,
+            message: format!("plugin i/o failed: {e}"),
+        };
+
+        let mut conn = self.conn.lock().await;
+        let id = conn.next_id;
+        conn.next_id += 1;
+
+        let mut line = serde_json::to_string(&Request {
+            jsonrpc: "2.0".to_string(),
+            id,
+            method: method.to_string(),
+            params: serde_json::to_value(params).map_err(|e| RpcError {
+                code: INVALID_PARAMS,
+                message: e.to_string(),
+            })?,
+        })
+        .map_err(|e| RpcError {
+            code: INVALID_PARAMS,
+            message: e.to_string(),
+        })?;
+        line.push('\n');
+        conn.writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(io_err)?;
+        conn.writer.flush().await.map_err(io_err)?;
+
+        // answers to earlier calls that were dropped before reading them arrive first
+        let resp = loop {
+            let Conn { reader, buf, .. } = &mut *conn;
+            reader.read_until(b'\n', buf).await.map_err(io_err)?;
+            if !buf.ends_with(b"\n") {
+                return Err(RpcError {
+                    code: PROVIDER_ERROR,
+                    message: "plugin exited".to_string(),
+                });
+            }
+            let line = std::mem::take(buf);
+            let resp: Response = serde_json::from_slice(&line).map_err(|e| RpcError {
+                code: PROVIDER_ERROR,
+                message: format!("invalid response from plugin: {e}"),
+            })?;
+            if resp.id >= id {
+                break resp;
+            }
+            tracing::debug!(
+                plugin = self.name.as_str(),
+                id = resp.id,
+                "discarding the answer to a dropped call"
+            );
+        };
+        drop(conn);
+
+        if resp.id != id {
+            return Err(RpcError {
+                code: PROVIDER_ERROR,
+                message: format!("plugin answered request {}, expected {id}", resp.id),
+            });
+        }
+        if let Some(err) = resp.error {
+            return Err(err);
+        }
+        serde_json::from_value(resp.result.unwrap_or_default()).map_err(|e| RpcError {
+            code: PROVIDER_ERROR,
+            message: format!("invalid result from plugin: {e}"),
+        })
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+fn to_err(pm: &PathMap, err: RpcError, as_err: fn(String, String) -> Error) -> Error {
+    if err.code == NOT_FOUND {
+        Error::NotFound {
+            path: pm.path.clone(),
+            msg: err.message,
+        }
+    } else {
+        as_err(pm.path.clone(), err.to_string())
+    }
+}
+
+#[async_trait]
+impl Provider for Plugin {
+    fn kind(&self) -> ProviderInfo {
+        ProviderInfo {
+            kind: ProviderKind::Plugin,
+            name: self.name.clone(),
+        }
+    }
+
+    async fn get(&self, pm: &PathMap) -> Result<Vec<KV>> {
+        let res: DataResult = self
+            .call(
+                "get",
+                MapParams {
+                    path_map: pm.clone(),
+                    data: None,
+                },
+            )
+            .await
+            .map_err(|e| to_err(pm, e, |path, msg| Error::GetError { path, msg }))?;
+        Ok(KV::from_data(&res.data, pm, &self.kind()))
+    }
+
+    async fn put(&self, pm: &PathMap, kvs: &[KV]) -> Result<()> {
+        let _: serde_json::Value = self
+            .call(
+                "put",
+                MapParams {
+                    path_map: pm.clone(),
+                    data: Some(KV::to_data(kvs)),
+                },
+            )
+            .await
+            .map_err(|e| to_err(pm, e, |path, msg| Error::PutError { path, msg }))?;
+        Ok(())
+    }
+
+    async fn del(&self, pm: &PathMap) -> Result<()> {
+        let _: serde_json::Value = self
+            .call(
+                "del",
+                MapParams {
+                    path_map: pm.clone(),
+                    data: None,
+                },
+            )
+            .await
+            .map_err(|e| to_err(pm, e, |path, msg| Error::DeleteError { path, msg }))?;
+        Ok(())
+    }
+}
+
+/// Serve a provider over the plugin protocol until `reader` closes. This is the plugin side of
+/// [`Plugin`], use it to build a plugin executable from any [`Provider`] (over stdin/stdout).
+///
+/// # Errors
+///
+/// This function will return an error if reading or writing the transport fails
+pub async fn serve<R, W>(
+    provider: &(dyn Provider + Send + Sync),
+    kind: &str,
+    mut reader: R,
+    mut writer: W,
+) -> Result<()>
+where
+    R: AsyncBufRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (id, outcome) = match serde_json::from_str::<Request>(&line) {
+            Ok(req) => (req.id, dispatch(provider, kind, req).await),
+            Err(e) => (
+                0,
+                Err(RpcError {
+                    code: INVALID_PARAMS,
+                    message: e.to_string(),
+                }),
+            ),
+        };
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(err) => (None, Some(err)),
+        };
+
+        let mut out = serde_json::to_string(&Response {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        })?;
+        out.push('\n');
+        writer.write_all(out.as_bytes()).await?;
+        writer.flush().await?;
+    }
+}
+
+async fn dispatch(
+    provider: &(dyn Provider + Send + Sync),
+    kind: &str,
+    req: Request,
+) -> std::result::Result<serde_json::Value, RpcError> {
+    fn params<T: DeserializeOwned>(value: serde_json::Value) -> std::result::Result<T, RpcError> {
+        serde_json::from_value(value).map_err(|e| RpcError {
+            code: INVALID_PARAMS,
+            message: e.to_string(),
+        })
+    }
+    fn provider_err(err: Error) -> RpcError {
+        match err {
+            Error::NotFound { msg, .. } => RpcError {
+                code: NOT_FOUND,
+                message: msg,
+            },
+            err => RpcError {
+                code: PROVIDER_ERROR,
+                message: err.to_string(),
+            },
+        }
+    }
+
+    let value = match req.method.as_str() {
+        "kind" => {
+            let p: KindParams = params(req.params)?;
+            if p.protocol_version != PROTOCOL_VERSION {
+                return Err(RpcError {
+                    code: INVALID_PARAMS,
+                    message: format!(
+                        "unsupported protocol version {}, this plugin speaks {PROTOCOL_VERSION}",
+                        p.protocol_version
+                    ),
+                });
+            }
+            serde_json::to_value(KindResult {
+                protocol_version: PROTOCOL_VERSION,
+                kind: kind.to_string(),
+            })
+        }
+        "get" => {
+            let p: MapParams = params(req.params)?;
+            let kvs = provider.get(&p.path_map).await.map_err(provider_err)?;
+            // answer with keys as stored, the caller applies the map's `keys`
+            let data = kvs
+                .into_iter()
+                .map(|kv| (kv.from_key, kv.value))
+                .collect::<BTreeMap<_, _>>();
+            serde_json::to_value(DataResult { data })
+        }
+        "put" => {
+            let p: MapParams = params(req.params)?;
+            let kvs = p
+                .data
+                .unwrap_or_default()
+                .iter()
+                .map(|(k, v)| KV::from_kv(k, v))
+                .collect::<Vec<_>>();
+            provider
+                .put(&p.path_map, &kvs)
+                .await
+                .map_err(provider_err)?;
+            Ok(serde_json::json!({}))
+        }
+        "del" => {
+            let p: MapParams = params(req.params)?;
+            provider.del(&p.path_map).await.map_err(provider_err)?;
+            Ok(serde_json::json!({}))
+        }
+        method => {
+            return Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("unknown method '{method}'"),
+            })
+        }
+    };
+    value.map_err(|e| RpcError {
+        code: PROVIDER_ERROR,
+        message: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::{serve, Plugin, PROTOCOL_VERSION};
+    use crate::{
+        config::PathMap,
+        providers::{inmem::Inmem, test_utils},
+        Provider,
+    };
+
+    async fn connect_inmem() -> Plugin {
+        let (client, server) = duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move {
+            let inmem = Inmem::new("plugin", None).unwrap();
+            serve(&inmem, "inmem", BufReader::new(server_read), server_write)
+                .await
+                .unwrap();
+        });
+        let (client_read, client_write) = tokio::io::split(client);
+        Plugin::connect("test", BufReader::new(client_read), client_write, None)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn conformance_test() {
+        let p = connect_inmem().await;
+        assert_eq!(p.plugin_kind, "inmem");
+
+        test_utils::ProviderTest::new(Box::new(p) as Box<dyn Provider + Send + Sync>)
+            .run()
+            .await;
+    }
+
+    #[tokio::test]
+    async fn skips_answers_to_dropped_calls() {
+        let (client, server) = duplex(64 * 1024);
+        let (server_read, mut server_write) = tokio::io::split(server);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(server_read).lines();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let req: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let id = req["id"].as_u64().unwrap();
+                // the first `get` is slow, its caller gives up before the answer
+                if id == 2 {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let result = if req["method"] == "kind" {
+                    json!({ "protocol_version": PROTOCOL_VERSION, "kind": "slow" })
+                } else {
+                    json!({ "data": { "call": id.to_string() } })
+                };
+                let resp = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+                server_write
+                    .write_all(format!("{resp}\n").as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+        let (client_read, client_write) = tokio::io::split(client);
+        let p = Plugin::connect("test", BufReader::new(client_read), client_write, None)
+            .await
+            .unwrap();
+
+        let pm = PathMap::from_path("any");
+        assert!(tokio::time::timeout(Duration::from_millis(10), p.get(&pm))
+            .await
+            .is_err());
+        let kvs = p.get(&pm).await.unwrap();
+        assert_eq!(kvs[0].value, "3");
+    }
+
+    #[tokio::test]
+    async fn missing_command() {
+        let err = Plugin::new(
+            "test",
+            Some(serde_json::json!({ "command": "teller-plugin-does-not-exist" })),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("cannot start plugin 'test'"));
+    }
+}
@@ -15,6 +15,8 @@
 //!
 //!
 #![allow(clippy::borrowed_box)]
+use std::collections::BTreeSet;
+
 use async_trait::async_trait;
 use aws_config::{self, BehaviorVersion};
 use aws_sdk_ssm as ssm;
@@ -24,8 +26,8 @@ use ssm::{
     error::SdkError, operation::delete_parameter::DeleteParameterError, types::ParameterType,
 };
 
-use super::ProviderKind;
-use crate::config::{PathMap, ProviderInfo, KV};
+use super::{list_entry, ProviderKind};
+use crate::config::{ListEntry, PathMap, ProviderInfo, KV};
 use crate::Provider;
 use crate::{Error, Result};
 
@@ -230,7 +232,7 @@ impl Provider for SSM {
         let paths = if pm.keys.is_empty() {
             let kvs = self.get(pm).await?;
             kvs.iter()
-                .map(|kv| join_path(&pm.path, &kv.key))
+                .map(|kv| join_path(&pm.path, &kv.from_key))
                 .collect::<Vec<_>>()
         } else {
             pm.keys
@@ -246,6 +248,32 @@ impl Provider for SSM {
 
         Ok(())
     }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        let resp = self
+            .client
+            .get_parameters_by_path()
+            .path(prefix)
+            .recursive(true)
+            .into_paginator()
+            .send()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .await
+            .map_err(|e| Error::ListError {
+                msg: e.to_string(),
+                path: prefix.to_string(),
+            })?;
+
+        let mut entries = BTreeSet::new();
+        for params in resp {
+            for p in params.parameters.unwrap_or_default() {
+                if let Some(entry) = list_entry(prefix, p.name().unwrap_or_default()) {
+                    entries.insert(entry);
+                }
+            }
+        }
+        Ok(entries.into_iter().collect())
+    }
 }
 
 #[cfg(test)]
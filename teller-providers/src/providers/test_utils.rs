@@ -207,7 +207,7 @@ impl ProviderTest {
         }
     }
 
-    async fn validate_get_selective(&self, path_tree: &HashMap<&str, Vec<KV>>) {
+    async fn validate_get_selective(&self, _path_tree: &HashMap<&str, Vec<KV>>) {
         let mut selective_pm = PathMap::from_path(&self.get_key_path(ROOT_PATH_A));
         selective_pm
             .keys
@@ -326,7 +326,7 @@ impl ProviderTest {
     ///
     /// 1. Delete specifics keys from a path by using adding keys list.
     /// 2. Verifies that the deletion operation is successful by run `get` again on the same path and expecting that
-    /// not see the deletion keys
+    ///    not see the deletion keys
     async fn validate_delete_keys(&self) {
         let mut path_path = PathMap::from_path(&self.get_key_path(ROOT_PATH_A));
 
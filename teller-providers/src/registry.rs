@@ -1,105 +1,254 @@
 use std::collections::{BTreeMap, HashMap};
 
-use crate::providers::ProviderKind;
-use crate::Result;
+use tokio::sync::OnceCell;
+
+use crate::cache::Cached;
+use crate::format::Formatted;
+use crate::providers::{ProviderKind, PROVIDER_KINDS};
+use crate::retry::Retrying;
 use crate::{config::ProviderCfg, Provider};
+use crate::{Error, Result};
+
+/// Builds a provider of a custom kind from its name and `options`
+pub type ProviderFactory = Box<
+    dyn Fn(&str, Option<serde_json::Value>) -> Result<Box<dyn Provider + Sync + Send>>
+        + Sync
+        + Send,
+>;
 
+/// Providers by name. Configured providers are created on first use, so a command only
+/// needs credentials and connections for the providers it touches.
 pub struct Registry {
+    configs: BTreeMap<String, ProviderCfg>,
+    providers: HashMap<String, OnceCell<Box<dyn Provider + Sync + Send>>>,
+    factories: HashMap<String, ProviderFactory>,
+}
+
+/// Builds a [`Registry`] that also knows about providers teller does not ship:
+/// ready-made instances registered by name, and factories for custom `kind`s.
+///
+/// ```no_run
+/// # async fn example(
+/// #     config: &std::collections::BTreeMap<String, teller_providers::config::ProviderCfg>,
+/// # ) -> teller_providers::Result<()> {
+/// use teller_providers::{providers::inmem::Inmem, registry::RegistryBuilder};
+///
+/// let registry = RegistryBuilder::new()
+///     .with_factory("acme", |name, opts| Ok(Box::new(Inmem::new(name, opts)?)))
+///     .build(config)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct RegistryBuilder {
     providers: HashMap<String, Box<dyn Provider + Sync + Send>>,
+    factories: HashMap<String, ProviderFactory>,
 }
 
-impl Registry {
-    /// Create a registry from config
+impl RegistryBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a ready-made provider under `name`. It takes the place of a configured
+    /// provider with the same name, whatever its `kind`.
+    #[must_use]
+    pub fn with_provider(mut self, name: &str, provider: Box<dyn Provider + Sync + Send>) -> Self {
+        self.providers.insert(name.to_string(), provider);
+        self
+    }
+
+    /// Register a factory for providers configured with `kind: <kind>`
+    #[must_use]
+    pub fn with_factory<F>(mut self, kind: &str, factory: F) -> Self
+    where
+        F: Fn(&str, Option<serde_json::Value>) -> Result<Box<dyn Provider + Sync + Send>>
+            + Sync
+            + Send
+            + 'static,
+    {
+        self.factories.insert(kind.to_string(), Box::new(factory));
+        self
+    }
+
+    /// Create a registry from config, using registered providers and factories first.
+    /// Other providers are only loaded on first use.
     ///
     /// # Errors
     ///
-    /// This function will return an error if any provider loading failed
-    pub async fn new(providers: &BTreeMap<String, ProviderCfg>) -> Result<Self> {
+    /// This function will return an error if a custom kind has no factory, or the
+    /// middleware of a registered provider cannot be set up
+    pub fn build(self, providers: &BTreeMap<String, ProviderCfg>) -> Result<Registry> {
         let mut loaded_providers = HashMap::new();
+        for (k, provider) in self.providers {
+            let provider = match providers.get(&k) {
+                Some(providercfg) => wrap(&k, providercfg, provider)?,
+                None => provider,
+            };
+            loaded_providers.insert(k, OnceCell::new_with(Some(provider)));
+        }
         for (k, provider) in providers {
-            let provider: Box<dyn Provider + Sync + Send> = match provider.kind {
-                ProviderKind::Inmem => Box::new(crate::providers::inmem::Inmem::new(
-                    k,
-                    provider.options.clone(),
-                )?),
-
-                #[cfg(feature = "dotenv")]
-                ProviderKind::Dotenv => Box::new(crate::providers::dotenv::Dotenv::new(
-                    k,
-                    provider
-                        .options
-                        .clone()
-                        .map(serde_json::from_value)
-                        .transpose()?,
-                )?),
-                #[cfg(feature = "hashicorp_vault")]
-                ProviderKind::Hashicorp => {
-                    Box::new(crate::providers::hashicorp_vault::Hashivault::new(
-                        k,
-                        provider
-                            .options
-                            .clone()
-                            .map(serde_json::from_value)
-                            .transpose()?,
-                    )?)
-                }
-                #[cfg(feature = "ssm")]
-                ProviderKind::SSM => {
-                    Box::new(crate::providers::ssm::SSM::new(k, provider.options.clone()).await?)
+            if let ProviderKind::Custom(kind) = &provider.kind {
+                if !loaded_providers.contains_key(k) && !self.factories.contains_key(kind) {
+                    return Err(unknown_kind(k, kind));
                 }
-                #[cfg(feature = "aws_secretsmanager")]
-                ProviderKind::AWSSecretsManager => Box::new(
-                    crate::providers::aws_secretsmanager::AWSSecretsManager::new(
-                        k,
-                        provider
-                            .options
-                            .clone()
-                            .map(serde_json::from_value)
-                            .transpose()?,
-                    )
-                    .await?,
-                ),
-                #[cfg(feature = "google_secretmanager")]
-                ProviderKind::GoogleSecretManager => Box::new(
-                    crate::providers::google_secretmanager::GoogleSecretManager::new(
-                        k,
-                        Box::new(crate::providers::google_secretmanager::GSMClient::new().await?)
-                            as Box<dyn crate::providers::google_secretmanager::GSM + Send + Sync>,
-                    ),
-                ),
-                #[cfg(feature = "hashicorp_consul")]
-                ProviderKind::HashiCorpConsul => {
-                    Box::new(crate::providers::hashicorp_consul::HashiCorpConsul::new(
-                        k,
-                        provider
-                            .options
-                            .clone()
-                            .map(serde_json::from_value)
-                            .transpose()?,
-                    )?)
-                }
-                #[cfg(feature = "etcd")]
-                ProviderKind::Etcd => Box::new(
-                    crate::providers::etcd::Etcd::new(
-                        k,
-                        provider
-                            .options
-                            .clone()
-                            .map(serde_json::from_value)
-                            .transpose()?,
-                    )
-                    .await?,
-                ),
-            };
-            loaded_providers.insert(k.clone(), provider);
+            }
+            loaded_providers.entry(k.clone()).or_default();
         }
-        Ok(Self {
+        Ok(Registry {
+            configs: providers.clone(),
             providers: loaded_providers,
+            factories: self.factories,
         })
     }
-    #[must_use]
+}
+
+async fn load(
+    factories: &HashMap<String, ProviderFactory>,
+    k: &str,
+    provider: &ProviderCfg,
+) -> Result<Box<dyn Provider + Sync + Send>> {
+    Ok(match &provider.kind {
+        ProviderKind::Inmem => Box::new(crate::providers::inmem::Inmem::new(
+            k,
+            provider.options.clone(),
+        )?),
+
+        #[cfg(feature = "dotenv")]
+        ProviderKind::Dotenv => Box::new(crate::providers::dotenv::Dotenv::new(
+            k,
+            provider
+                .options
+                .clone()
+                .map(serde_json::from_value)
+                .transpose()?,
+        )?),
+        #[cfg(feature = "hashicorp_vault")]
+        ProviderKind::Hashicorp => Box::new(crate::providers::hashicorp_vault::Hashivault::new(
+            k,
+            provider
+                .options
+                .clone()
+                .map(serde_json::from_value)
+                .transpose()?,
+        )?),
+        #[cfg(feature = "ssm")]
+        ProviderKind::SSM => {
+            Box::new(crate::providers::ssm::SSM::new(k, provider.options.clone()).await?)
+        }
+        #[cfg(feature = "aws_secretsmanager")]
+        ProviderKind::AWSSecretsManager => Box::new(
+            crate::providers::aws_secretsmanager::AWSSecretsManager::new(
+                k,
+                provider
+                    .options
+                    .clone()
+                    .map(serde_json::from_value)
+                    .transpose()?,
+            )
+            .await?,
+        ),
+        #[cfg(feature = "google_secretmanager")]
+        ProviderKind::GoogleSecretManager => Box::new(
+            crate::providers::google_secretmanager::GoogleSecretManager::new(
+                k,
+                Box::new(crate::providers::google_secretmanager::GSMClient::new().await?)
+                    as Box<dyn crate::providers::google_secretmanager::GSM + Send + Sync>,
+            ),
+        ),
+        #[cfg(feature = "hashicorp_consul")]
+        ProviderKind::HashiCorpConsul => {
+            Box::new(crate::providers::hashicorp_consul::HashiCorpConsul::new(
+                k,
+                provider
+                    .options
+                    .clone()
+                    .map(serde_json::from_value)
+                    .transpose()?,
+            )?)
+        }
+        #[cfg(feature = "etcd")]
+        ProviderKind::Etcd => Box::new(
+            crate::providers::etcd::Etcd::new(
+                k,
+                provider
+                    .options
+                    .clone()
+                    .map(serde_json::from_value)
+                    .transpose()?,
+            )
+            .await?,
+        ),
+        #[cfg(feature = "plugin")]
+        ProviderKind::Plugin => {
+            Box::new(crate::providers::plugin::Plugin::new(k, provider.options.clone()).await?)
+        }
+        ProviderKind::Custom(kind) => {
+            let factory = factories.get(kind).ok_or_else(|| unknown_kind(k, kind))?;
+            factory(k, provider.options.clone())?
+        }
+    })
+}
+
+fn unknown_kind(k: &str, kind: &str) -> Error {
+    Error::CreateProviderError(format!(
+        "provider '{k}' has unknown kind '{kind}', expected one of: {}",
+        *PROVIDER_KINDS
+    ))
+}
+
+/// Apply the middleware configured for a provider. Retries sit under the cache, so cache
+/// hits skip them and stale entries are only served once retries are exhausted. Documents
+/// of maps with a `format` are exploded under the cache too, so writes merge into the
+/// stored document rather than a cached copy of it.
+fn wrap(
+    k: &str,
+    provider: &ProviderCfg,
+    mut loaded: Box<dyn Provider + Sync + Send>,
+) -> Result<Box<dyn Provider + Sync + Send>> {
+    if let Some(retry) = &provider.retry {
+        loaded = Box::new(Retrying::new(loaded, retry));
+    }
+    loaded = Box::new(Formatted::new(loaded));
+    if let Some(cache) = &provider.cache {
+        loaded = Box::new(
+            Cached::new(k, loaded, cache)?.for_backend(&provider.kind, provider.options.as_ref()),
+        );
+    }
+    Ok(loaded)
+}
+
+impl Registry {
+    /// Create a registry from config, providers are loaded on first use
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a provider has an unknown kind
+    pub fn new(providers: &BTreeMap<String, ProviderCfg>) -> Result<Self> {
+        RegistryBuilder::new().build(providers)
+    }
+
+    /// Get a provider by name, loading it on first use. Loading is retried on the next
+    /// call when it fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if loading the provider fails
     #[allow(clippy::borrowed_box)]
-    pub fn get(&self, name: &str) -> Option<&Box<dyn Provider + Sync + Send>> {
-        self.providers.get(name)
+    pub async fn get(&self, name: &str) -> Result<Option<&Box<dyn Provider + Sync + Send>>> {
+        let Some(cell) = self.providers.get(name) else {
+            return Ok(None);
+        };
+        let Some(providercfg) = self.configs.get(name) else {
+            return Ok(cell.get());
+        };
+        let provider = cell
+            .get_or_try_init(|| async {
+                let loaded = load(&self.factories, name, providercfg).await?;
+                wrap(name, providercfg, loaded)
+            })
+            .await?;
+        Ok(Some(provider))
     }
 }
@@ -0,0 +1,340 @@
+//! Timeouts and retries for provider calls
+//!
+//! ## Example configuration
+//!
+//! ```yaml
+//! providers:
+//!  consul1:
+//!    kind: hashicorp_consul
+//!    retry:
+//!      timeout: 5s          # per call, each attempt
+//!      max_attempts: 4      # including the first call
+//!      initial_backoff: 200ms
+//!      max_backoff: 5s
+//!    maps: ...
+//! ```
+//!
+//! Only errors classified as retryable by [`Error::is_retryable`] are retried, with an
+//! exponential backoff and full jitter between attempts. Writes are not retried after a
+//! timeout, the backend may have carried out the first one.
+#![allow(clippy::borrowed_box)]
+use std::{future::Future, time::Duration};
+
+use async_trait::async_trait;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::{ListEntry, PathMap, ProviderInfo, VersionInfo, KV},
+    Error, Provider, Result,
+};
+
+const fn default_max_attempts() -> u32 {
+    3
+}
+
+const fn default_initial_backoff() -> Duration {
+    Duration::from_millis(200)
+}
+
+const fn default_max_backoff() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct RetryCfg {
+    /// Limit for each attempt, none waits forever
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<Duration>,
+    /// Attempts in total, including the first call
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Backoff before the first retry, doubled for every following one
+    #[serde(default = "default_initial_backoff", with = "humantime_serde")]
+    pub initial_backoff: Duration,
+    #[serde(default = "default_max_backoff", with = "humantime_serde")]
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryCfg {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            max_attempts: default_max_attempts(),
+            initial_backoff: default_initial_backoff(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+impl RetryCfg {
+    /// The capped exponential backoff before retry number `retry` (starting at 0), with full
+    /// jitter: a random duration between zero and the backoff
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let mut random = [0u8; 4];
+        if SystemRandom::new().fill(&mut random).is_err() {
+            return ceiling;
+        }
+        ceiling.mul_f64(f64::from(u32::from_le_bytes(random)) / f64::from(u32::MAX))
+    }
+}
+
+/// A provider wrapper applying a per-call timeout and retrying retryable errors
+pub struct Retrying {
+    inner: Box<dyn Provider + Send + Sync>,
+    cfg: RetryCfg,
+}
+
+impl Retrying {
+    #[must_use]
+    pub fn new(inner: Box<dyn Provider + Send + Sync>, cfg: &RetryCfg) -> Self {
+        Self {
+            inner,
+            cfg: cfg.clone(),
+        }
+    }
+
+    async fn call<'a, T, F, Fut>(&'a self, path: &str, write: bool, f: F) -> Result<T>
+    where
+        F: Fn(&'a (dyn Provider + Send + Sync)) -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send + 'a,
+    {
+        let mut retry = 0;
+        loop {
+            let attempt = f(self.inner.as_ref());
+            let res = match self.cfg.timeout {
+                Some(timeout) => {
+                    tokio::time::timeout(timeout, attempt)
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(Error::Timeout {
+                                path: path.to_string(),
+                                msg: format!("no response after {}ms", timeout.as_millis()),
+                            })
+                        })
+                }
+                None => attempt.await,
+            };
+            match res {
+                Err(err)
+                    if err.is_retryable()
+                        && !(write && err.is_timeout())
+                        && retry + 1 < self.cfg.max_attempts =>
+                {
+                    tokio::time::sleep(self.cfg.backoff(retry)).await;
+                    retry += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for Retrying {
+    fn kind(&self) -> ProviderInfo {
+        self.inner.kind()
+    }
+
+    async fn get(&self, pm: &PathMap) -> Result<Vec<KV>> {
+        self.call(&pm.path, false, |p| p.get(pm)).await
+    }
+
+    async fn put(&self, pm: &PathMap, kvs: &[KV]) -> Result<()> {
+        self.call(&pm.path, true, |p| p.put(pm, kvs)).await
+    }
+
+    async fn del(&self, pm: &PathMap) -> Result<()> {
+        self.call(&pm.path, true, |p| p.del(pm)).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListEntry>> {
+        self.call(prefix, false, |p| p.list(prefix)).await
+    }
+
+    async fn history(&self, pm: &PathMap) -> Result<Vec<VersionInfo>> {
+        self.call(&pm.path, false, |p| p.history(pm)).await
+    }
+
+    async fn watch(&self, pm: &PathMap, timeout: Duration) -> Result<bool> {
+        // retrying a watch would wait again, callers already loop
+        self.inner.watch(pm, timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{RetryCfg, Retrying};
+    use crate::{
+        config::{PathMap, ProviderInfo, KV},
+        Error, Provider, Result,
+    };
+
+    /// Fails `get` with `error` for the first `failures` calls, sleeps `delay` on every call
+    struct Failing {
+        failures: u32,
+        error: fn() -> Error,
+        delay: Duration,
+        calls: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl Provider for Failing {
+        fn kind(&self) -> ProviderInfo {
+            ProviderInfo::default()
+        }
+        async fn get(&self, _pm: &PathMap) -> Result<Vec<KV>> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            if call < self.failures {
+                return Err((self.error)());
+            }
+            Ok(vec![KV::from_kv("A", "1")])
+        }
+        async fn put(&self, _pm: &PathMap, _kvs: &[KV]) -> Result<()> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            if call < self.failures {
+                return Err((self.error)());
+            }
+            Ok(())
+        }
+        async fn del(&self, _pm: &PathMap) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn retrying(failures: u32, error: fn() -> Error, delay: Duration) -> Retrying {
+        retrying_counted(failures, error, delay, Arc::default())
+    }
+
+    fn retrying_counted(
+        failures: u32,
+        error: fn() -> Error,
+        delay: Duration,
+        calls: Arc<AtomicU32>,
+    ) -> Retrying {
+        let failing = Failing {
+            failures,
+            error,
+            delay,
+            calls,
+        };
+        Retrying::new(
+            Box::new(failing),
+            &RetryCfg {
+                timeout: Some(Duration::from_millis(50)),
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(5),
+            },
+        )
+    }
+
+    fn unavailable() -> Error {
+        Error::GetError {
+            path: "app".to_string(),
+            msg: "503 Service Unavailable".to_string(),
+        }
+    }
+
+    fn not_found() -> Error {
+        Error::NotFound {
+            path: "app".to_string(),
+            msg: "not found".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_errors() {
+        let pm = PathMap::from_path("app");
+        assert!(retrying(2, unavailable, Duration::ZERO)
+            .get(&pm)
+            .await
+            .is_ok());
+        assert!(retrying(3, unavailable, Duration::ZERO)
+            .get(&pm)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_fail_at_once() {
+        let p = retrying(1, not_found, Duration::ZERO);
+        assert!(matches!(
+            p.get(&PathMap::from_path("app")).await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn times_out_hung_calls() {
+        let p = retrying(0, not_found, Duration::from_secs(10));
+        assert!(matches!(
+            p.get(&PathMap::from_path("app")).await,
+            Err(Error::Timeout { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn does_not_repeat_timed_out_writes() {
+        let calls = Arc::default();
+        let p = retrying_counted(0, not_found, Duration::from_secs(10), Arc::clone(&calls));
+        assert!(p.put(&PathMap::from_path("app"), &[]).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn classifies_errors() {
+        assert!(unavailable().is_retryable());
+        assert!(!not_found().is_retryable());
+        assert!(Error::IO(std::io::ErrorKind::ConnectionRefused.into()).is_retryable());
+        assert!(!Error::IO(std::io::ErrorKind::PermissionDenied.into()).is_retryable());
+        assert!(!Error::GetError {
+            path: "app".to_string(),
+            msg: "permission denied".to_string(),
+        }
+        .is_retryable());
+        assert!(!Error::GetError {
+            path: "app".to_string(),
+            msg: "key app/5030 not found on host:15032".to_string(),
+        }
+        .is_retryable());
+        assert!(Error::GetError {
+            path: "app".to_string(),
+            msg: "ThrottlingException: Rate exceeded".to_string(),
+        }
+        .is_retryable());
+        assert!(!Error::GetError {
+            path: "app".to_string(),
+            msg: "invalid value for key SESSION_401".to_string(),
+        }
+        .is_auth_error());
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let cfg = RetryCfg::default();
+        for retry in 0..40 {
+            assert!(cfg.backoff(retry) <= cfg.max_backoff);
+        }
+    }
+}